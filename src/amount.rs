@@ -0,0 +1,81 @@
+use std::fmt;
+use std::str::FromStr;
+
+use failure::format_err;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::Result;
+
+/// Number of decimal places of a coin
+pub const DECIMALS: usize = 8;
+/// Number of base units in one coin
+pub const COIN: u64 = 100_000_000;
+
+/// Amount is a quantity of coins counted in base units. Arithmetic on it is
+/// checked, so amounts can neither be negative nor overflow
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Amount(u64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+
+    pub const fn from_base_units(units: u64) -> Amount {
+        Amount(units)
+    }
+
+    pub fn from_coins(coins: u64) -> Option<Amount> {
+        coins.checked_mul(COIN).map(Amount)
+    }
+
+    pub const fn base_units(self) -> u64 {
+        self.0
+    }
+
+    pub fn checked_add(self, other: Amount) -> Option<Amount> {
+        self.0.checked_add(other.0).map(Amount)
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        self.0.checked_sub(other.0).map(Amount)
+    }
+
+    /// CheckedSum adds up amounts, returning None on overflow
+    pub fn checked_sum<I: IntoIterator<Item = Amount>>(amounts: I) -> Option<Amount> {
+        amounts
+            .into_iter()
+            .try_fold(Amount::ZERO, |total, amount| total.checked_add(amount))
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{:0width$}", self.0 / COIN, self.0 % COIN, width = DECIMALS)
+    }
+}
+
+impl FromStr for Amount {
+    type Err = failure::Error;
+
+    /// FromStr parses a decimal number of coins such as "1.5"
+    fn from_str(s: &str) -> Result<Amount> {
+        let (whole, frac) = s.split_once('.').unwrap_or((s, ""));
+        let is_digits = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+        if (whole.is_empty() && frac.is_empty())
+            || frac.len() > DECIMALS
+            || !is_digits(whole)
+            || !is_digits(frac)
+        {
+            return Err(format_err!("Invalid amount {}", s));
+        }
+        let too_large = || format_err!("Amount {} is too large", s);
+        let whole: u64 = if whole.is_empty() { 0 } else { whole.parse().map_err(|_| too_large())? };
+        let frac: u64 = if frac.is_empty() {
+            0
+        } else {
+            format!("{:0<width$}", frac, width = DECIMALS).parse()?
+        };
+        Amount::from_coins(whole)
+            .and_then(|amount| amount.checked_add(Amount(frac)))
+            .ok_or_else(too_large)
+    }
+}
@@ -1,20 +1,46 @@
-use crate::{errors::Result, transaction::Transaction};
+use crate::{errors::Result, pow, transaction::Transaction};
 use std::time::SystemTime;
 use crypto::digest::Digest;
 use crypto::sha2::Sha256;
+use failure::format_err;
 use log::info;
+use merkle_cbt::merkle_tree::{Merge, CBMT};
 use serde::{Deserialize, Serialize};
 
-const TARGET_HEXT: usize = 4;
+const BLOCK_VERSION: i32 = 1;
 
+/// BlockHeader holds the fields covered by the proof of work
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Block {
+pub struct BlockHeader {
+    version: i32,
+    prev_block_hash: String,
+    merkle_root: String,
     timestamp: u128,
+    bits: u32,
+    nonce: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Block {
+    header: BlockHeader,
     transactions: Vec<Transaction>,
-    prev_block_hash: String,
     hash: String,
     height: i32,
-    nonce: i32,
+}
+
+struct MergeTx {}
+
+impl Merge for MergeTx {
+    type Item = [u8; 32];
+
+    fn merge(left: &Self::Item, right: &Self::Item) -> Self::Item {
+        let mut hasher = Sha256::new();
+        hasher.input(left);
+        hasher.input(right);
+        let mut data = [0u8; 32];
+        hasher.result(&mut data);
+        data
+    }
 }
 
 impl Block {
@@ -27,63 +53,118 @@ impl Block {
     }
 
     pub fn get_prev_hash(&self) -> String {
-        self.prev_block_hash.clone()
+        self.header.prev_block_hash.clone()
     }
 
     pub fn get_transaction(&self) -> &Vec<Transaction> {
         &self.transactions
     }
 
-    pub fn new_genesis_block(coinbase: Transaction) -> Block {
-        Block::new_block(vec![coinbase], String::new(), 0).unwrap()
+    pub fn get_timestamp(&self) -> u128 {
+        self.header.timestamp
     }
 
-    pub fn new_block(data: Vec<Transaction>, prev_block_hash: String, height: i32) -> Result<Block> {
-        let timestamp = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)?
-            .as_millis();
+    pub fn get_bits(&self) -> u32 {
+        self.header.bits
+    }
+
+    pub fn new_genesis_block(coinbase: Transaction, bits: u32) -> Block {
+        Block::new_block(vec![coinbase], String::new(), 0, bits, 0).unwrap()
+    }
+
+    /// NewBlock mines a block whose timestamp is the current time, or
+    /// min_timestamp if the clock is behind it
+    pub fn new_block(
+        data: Vec<Transaction>,
+        prev_block_hash: String,
+        height: i32,
+        bits: u32,
+        min_timestamp: u128,
+    ) -> Result<Block> {
         let mut block = Block {
-            timestamp: timestamp,
+            header: BlockHeader {
+                version: BLOCK_VERSION,
+                prev_block_hash,
+                merkle_root: merkle_root(&data)?,
+                timestamp: now()?.max(min_timestamp),
+                bits,
+                nonce: 0,
+            },
             transactions: data,
-            prev_block_hash,
             hash: String::new(),
             height,
-            nonce: 0,
         };
-        block.run_proof_of_work()?;
+        block.run_proof_of_work(min_timestamp)?;
         Ok(block)
     }
 
-    fn run_proof_of_work(&mut self) -> Result<()> {
+    /// CheckProofOfWork checks that the stored hash matches the block header
+    /// and satisfies the difficulty target of the block
+    pub fn check_proof_of_work(&self, limit_bits: u32) -> Result<bool> {
+        Ok(self.calculate_hash()? == self.hash
+            && pow::check_proof_of_work(&self.hash, self.header.bits, limit_bits)?)
+    }
+
+    /// CheckMerkleRoot checks that the header commits to the block transactions
+    pub fn check_merkle_root(&self) -> Result<bool> {
+        Ok(merkle_root(&self.transactions)? == self.header.merkle_root)
+    }
+
+    fn run_proof_of_work(&mut self, min_timestamp: u128) -> Result<()> {
         info!("Mining the block");
         while !self.validate()? {
-            self.nonce += 1;
+            match self.header.nonce.checked_add(1) {
+                Some(nonce) => self.header.nonce = nonce,
+                None => {
+                    self.header.nonce = 0;
+                    self.header.timestamp = now()?.max(min_timestamp);
+                }
+            }
         }
-        let data = self.prepare_hash_data()?;
-        let mut hasher = Sha256::new();
-        hasher.input(&data[..]);
-        self.hash = hasher.result_str();
+        self.hash = self.calculate_hash()?;
         Ok(())
     }
 
     fn prepare_hash_data(&self) -> Result<Vec<u8>> {
-        let content = (
-            self.prev_block_hash.clone(),
-            self.transactions.clone(),
-            self.timestamp,
-            TARGET_HEXT,
-            self.nonce
-        );
-        let bytes = bincode::serialize(&content)?;
+        let bytes = bincode::serialize(&self.header)?;
         Ok(bytes)
     }
 
-    fn validate(&self) -> Result<bool> {
-        let data: Vec<u8> = self.prepare_hash_data()?;
+    fn calculate_hash(&self) -> Result<String> {
+        let data = self.prepare_hash_data()?;
         let mut hasher = Sha256::new();
         hasher.input(&data[..]);
-        let mut vec1: Vec<u8> = vec![];
-        vec1.resize(TARGET_HEXT, '0' as u8);
-        Ok(&hasher.result_str()[0..TARGET_HEXT] == String::from_utf8(vec1)?)
+        Ok(hasher.result_str())
+    }
+
+    fn validate(&self) -> Result<bool> {
+        pow::meets_target(&self.calculate_hash()?, self.header.bits)
+    }
+}
+
+/// MerkleRoot commits to both the ids and the witness hashes of the
+/// transactions: it merges the merkle roots built over each of them
+fn merkle_root(transactions: &[Transaction]) -> Result<String> {
+    let mut id_leaves = Vec::new();
+    let mut witness_leaves = Vec::new();
+    for tx in transactions {
+        id_leaves.push(decode_leaf(&tx.id)?);
+        witness_leaves.push(decode_leaf(&tx.witness_hash()?)?);
     }
+    let id_root = CBMT::<[u8; 32], MergeTx>::build_merkle_root(&id_leaves);
+    let witness_root = CBMT::<[u8; 32], MergeTx>::build_merkle_root(&witness_leaves);
+    Ok(hex::encode(MergeTx::merge(&id_root, &witness_root)))
+}
+
+fn decode_leaf(hash: &str) -> Result<[u8; 32]> {
+    let mut leaf = [0u8; 32];
+    hex::decode_to_slice(hash, &mut leaf)
+        .map_err(|e| format_err!("Invalid transaction hash {}: {}", hash, e))?;
+    Ok(leaf)
+}
+
+fn now() -> Result<u128> {
+    Ok(SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_millis())
 }
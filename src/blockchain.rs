@@ -1,21 +1,49 @@
 use std::collections::HashMap;
-use std::vec;
+use std::time::SystemTime;
 
 use failure::format_err;
 use log::info;
+use serde::{Deserialize, Serialize};
 
+use crate::amount::Amount;
 use crate::block::Block;
-use crate::errors::Result;
+use crate::config::Config;
+use crate::errors::{BlockError, Result};
+use crate::pow::{self, U256};
+use crate::monetary;
 use crate::transaction::Transaction;
-use crate::tx::TXOutputs;
+use crate::utxoset::BlockUndo;
+use crate::wallet::Wallet;
 
-const GENESIS_COINBASE_DATA: &str =
-    "The Times 03/Jan/2009 Chancellor on brink of second bailout for banks";
+/// Number of previous blocks used to compute the median time past
+const MEDIAN_TIME_SPAN: usize = 11;
+/// How far ahead of the local clock a block timestamp may be, in milliseconds
+const MAX_FUTURE_BLOCK_TIME: u128 = 2 * 60 * 60 * 1000;
+const CHAINWORK_TREE: &str = "chainwork";
+const UNDO_TREE: &str = "undo";
+const TXINDEX_TREE: &str = "txindex";
+const HEIGHTS_TREE: &str = "heights";
+
+/// TxLocation is the block of the active chain holding a transaction and
+/// the position of the transaction in it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TxLocation {
+    pub block_hash: String,
+    pub position: u32,
+}
+
+/// ChainUpdate lists the blocks removed from and added to the active chain
+#[derive(Debug, Clone, Default)]
+pub struct ChainUpdate {
+    pub disconnected: Vec<Block>,
+    pub connected: Vec<Block>,
+}
 
 #[derive(Debug, Clone)]
 pub struct Blockchain {
     current_hash: String,
     db: sled::Db,
+    config: Config,
 }
 
 pub struct BlockchainIter<'a> {
@@ -23,21 +51,37 @@ pub struct BlockchainIter<'a> {
     bc: &'a Blockchain,
 }
 
+/// BlockchainRangeIter walks the active chain forward between two heights
+pub struct BlockchainRangeIter<'a> {
+    next_height: i32,
+    end_height: i32,
+    bc: &'a Blockchain,
+}
+
 impl Blockchain {
     /// NewBlockchain creates a new Blockchain db
-    pub fn new() -> Result<Blockchain> {
+    pub fn new(config: &Config) -> Result<Blockchain> {
         info!("open blockchain");
 
-        let db = sled::open("data/blocks")?;
+        let db = sled::open(config.blocks_path())?;
         let hash = db
             .get("LAST")?
             .expect("Must create a new block database first");
         info!("Found block database.");
         let lashash = String::from_utf8(hash.to_vec())?;
-        Ok(Blockchain {
+        let bc = Blockchain {
             current_hash: lashash.clone(),
             db,
-        })
+            config: config.clone(),
+        };
+        if bc.get_block_hash(bc.get_best_height()?)? != Some(lashash) {
+            bc.rebuild_height_index()?;
+        }
+        Ok(bc)
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
     }
 
     fn get_prev_txs(&self, tx: &Transaction) -> Result<HashMap<String, Transaction>> {
@@ -56,162 +100,449 @@ impl Blockchain {
             return Ok(-1);
         };
         let last_data = self.db.get(lasthash)?.unwrap();
-        let last_block: Block = bincode::deserialize(&last_data.to_vec())?;
+        let last_block: Block = bincode::deserialize(&last_data)?;
         Ok(last_block.get_height())
     }
 
-    pub fn get_block_hashs(&self) -> Vec<String> {
+    /// GetBlockHashs returns the hashes of the active chain from the genesis block
+    pub fn get_block_hashs(&self) -> Result<Vec<String>> {
         let mut list = Vec::new();
-        for b in self.iter() {
-            list.push(b.get_hash());
+        for height in 0..=self.get_best_height()? {
+            if let Some(hash) = self.get_block_hash(height)? {
+                list.push(hash);
+            }
+        }
+        Ok(list)
+    }
+
+    /// GetBlockHash returns the hash of the block at height in the active chain
+    pub fn get_block_hash(&self, height: i32) -> Result<Option<String>> {
+        if height < 0 {
+            return Ok(None);
         }
-        list
+        match self.db.open_tree(HEIGHTS_TREE)?.get((height as u32).to_be_bytes())? {
+            Some(hash) => Ok(Some(String::from_utf8(hash.to_vec())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// GetBlockByHeight returns the block at height in the active chain
+    pub fn get_block_by_height(&self, height: i32) -> Result<Option<Block>> {
+        match self.get_block_hash(height)? {
+            Some(hash) => Ok(Some(self.get_block(&hash)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// RebuildHeightIndex indexes the active chain by height, walking back from the tip
+    fn rebuild_height_index(&self) -> Result<()> {
+        info!("Rebuilding height index");
+        self.db.drop_tree(HEIGHTS_TREE)?;
+        let heights = self.db.open_tree(HEIGHTS_TREE)?;
+        for block in self.iter() {
+            heights.insert((block.get_height() as u32).to_be_bytes(), block.get_hash().as_bytes())?;
+        }
+        heights.flush()?;
+        Ok(())
     }
 
     /// GetBlock finds a block by its hash and returns it
     pub fn get_block(&self, block_hash: &str) -> Result<Block> {
-        let data = self.db.get(block_hash)?.unwrap();
-        let block = bincode::deserialize(&data.to_vec())?;
+        let data = self
+            .db
+            .get(block_hash)?
+            .ok_or_else(|| format_err!("Block {} is not found", block_hash))?;
+        let block = bincode::deserialize(&data)?;
         Ok(block)
     }
 
     /// CreateBlockchain creates a new blockchain DB
-    pub fn create_blockchain(address: String) -> Result<Blockchain> {
-        info!("Creating new blockchain...");
+    pub fn create_blockchain(config: &Config, address: String) -> Result<Blockchain> {
+        info!("Creating new {} blockchain...", config.network().name());
 
-        let db = sled::open("data/blocks")?;
+        let db = sled::open(config.blocks_path())?;
         info!("Creating new block database...");
-        let cbtx = Transaction::new_coinbase(address, String::from(GENESIS_COINBASE_DATA))?;
-        let genesis: Block = Block::new_genesis_block(cbtx);
-        db.insert(genesis.get_hash(), bincode::serialize(&genesis)?)?;
-        db.insert("LAST", genesis.get_hash().as_bytes())?;
-        let bc = Blockchain {
-            current_hash: genesis.get_hash(),
+        let data = config.network().genesis_coinbase_data();
+        let reward = monetary::block_subsidy(0, config.network());
+        let cbtx = Transaction::new_coinbase(address, String::from(data), 0, reward)?;
+        let genesis: Block = Block::new_genesis_block(cbtx, config.network().initial_bits());
+        let mut bc = Blockchain {
+            current_hash: String::new(),
             db,
+            config: config.clone(),
         };
-        bc.db.flush()?;
+        bc.store_block(&genesis)?;
+        bc.connect_tip(&genesis)?;
         Ok(bc)
     }
 
+    /// MineBlock mines a block of transactions on top of the tip. Callers
+    /// validate the transactions against the UTXO set first
     pub fn mine_block(&mut self, transactions: Vec<Transaction>) -> Result<Block> {
         info!("Mine a new block");
 
-        for tx in &transactions {
-            if !self.verify_transaction(tx)? {
-                return Err(format_err!("ERROR: Invalid transaction"));
-            }
-        }
-
-        let lasthash = self.db.get("LAST")?.unwrap();
+        let lasthash = String::from_utf8(self.db.get("LAST")?.unwrap().to_vec())?;
+        let bits = self.get_next_bits(&lasthash)?;
+        // blocks mined faster than the clock ticks must still be newer than
+        // the median time past, or peers reject them
+        let min_timestamp = self.get_median_time_past(&lasthash)? + 1;
 
         let newblock = Block::new_block(
             transactions,
-            String::from_utf8(lasthash.to_vec())?,
+            lasthash,
             self.get_best_height()? + 1,
+            bits,
+            min_timestamp,
         )?;
-        self.db.insert(newblock.get_hash(), bincode::serialize(&newblock)?)?;
-        self.db.insert("LAST", newblock.get_hash().as_bytes())?;
-        self.db.flush()?;
-
-        self.current_hash = newblock.get_hash();
+        self.check_block(&newblock)?;
+        self.store_block(&newblock)?;
+        self.connect_tip(&newblock)?;
         Ok(newblock)
     }
 
+    /// AddBlock stores a block received from a peer after checking it against
+    /// its parent. It does not change the active chain
     pub fn add_block(&mut self, block: Block) -> Result<()> {
-        let data = bincode::serialize(&block)?;
-        if let Some(_) = self.db.get(block.get_hash())? {
+        if self.has_block(&block.get_hash())? {
             return Ok(());
         }
-        self.db.insert(block.get_hash(), data)?;
+        self.check_block(&block)?;
+        self.store_block(&block)?;
+        self.db.flush()?;
+        Ok(())
+    }
 
-        let lastheight = self.get_best_height()?;
-        if block.get_height() > lastheight {
-            self.db.insert("LAST", block.get_hash().as_bytes())?;
-            self.current_hash = block.get_hash();
-            self.db.flush()?;
+    /// StoreBlock writes a block together with the total work of the chain ending at it
+    fn store_block(&self, block: &Block) -> Result<()> {
+        let mut work = pow::block_work(block.get_bits())?;
+        if !block.get_prev_hash().is_empty() {
+            work = work + self.get_chainwork(&block.get_prev_hash())?;
         }
+        self.db.insert(block.get_hash(), bincode::serialize(block)?)?;
+        self.db
+            .open_tree(CHAINWORK_TREE)?
+            .insert(block.get_hash(), bincode::serialize(&work)?)?;
         Ok(())
     }
-    
-    /// FindUTXO finds and returns all unspent transaction outputs
-    pub fn find_utxo(&self) -> HashMap<String, TXOutputs> {
-        let mut utxos: HashMap<String, TXOutputs> = HashMap::new();
-        let mut spent_txos: HashMap<String, Vec<i32>> = HashMap::new();
 
-        for block in self.iter() {
+    /// RemoveBlock deletes a stored block that turned out to be invalid
+    pub fn remove_block(&self, block_hash: &str) -> Result<()> {
+        self.db.remove(block_hash)?;
+        self.db.open_tree(CHAINWORK_TREE)?.remove(block_hash)?;
+        self.db.open_tree(UNDO_TREE)?.remove(block_hash)?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    /// PutUndo stores the undo record written when the block was connected
+    pub fn put_undo(&self, block_hash: &str, undo: &BlockUndo) -> Result<()> {
+        self.db
+            .open_tree(UNDO_TREE)?
+            .insert(block_hash, bincode::serialize(undo)?)?;
+        Ok(())
+    }
+
+    /// GetUndo returns the undo record of a block, if it was ever connected
+    pub fn get_undo(&self, block_hash: &str) -> Result<Option<BlockUndo>> {
+        match self.db.open_tree(UNDO_TREE)?.get(block_hash)? {
+            Some(data) => Ok(Some(bincode::deserialize(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// GetChainwork returns the total work of the chain ending at block_hash
+    pub fn get_chainwork(&self, block_hash: &str) -> Result<U256> {
+        match self.db.open_tree(CHAINWORK_TREE)?.get(block_hash)? {
+            Some(data) => Ok(bincode::deserialize(&data)?),
+            None => Err(format_err!("Chainwork of block {} is not found", block_hash)),
+        }
+    }
+
+    /// HasMoreWork checks whether the chain ending at block_hash has more
+    /// cumulative work than the active chain
+    pub fn has_more_work(&self, block_hash: &str) -> Result<bool> {
+        Ok(self.get_chainwork(block_hash)? > self.get_chainwork(&self.current_hash)?)
+    }
+
+    /// ConnectTip makes block, a child of the current tip, the last block of
+    /// the active chain and adds its transactions to the transaction index
+    pub fn connect_tip(&mut self, block: &Block) -> Result<()> {
+        if self.txindex_enabled()? {
+            index_transactions(&self.db.open_tree(TXINDEX_TREE)?, block)?;
+        }
+        self.db
+            .open_tree(HEIGHTS_TREE)?
+            .insert((block.get_height() as u32).to_be_bytes(), block.get_hash().as_bytes())?;
+        self.set_tip(&block.get_hash())
+    }
+
+    /// DisconnectTip removes the tip block from the active chain and its
+    /// transactions from the transaction index
+    pub fn disconnect_tip(&mut self, block: &Block) -> Result<()> {
+        if self.txindex_enabled()? {
+            let txindex = self.db.open_tree(TXINDEX_TREE)?;
             for tx in block.get_transaction() {
-                for index in 0..tx.vout.len() {
-                    if let Some(ids) = spent_txos.get(&tx.id) {
-                        if ids.contains(&(index as i32)) {
-                            continue;
-                        }
-                    }
+                txindex.remove(tx.id.as_bytes())?;
+            }
+        }
+        self.db
+            .open_tree(HEIGHTS_TREE)?
+            .remove((block.get_height() as u32).to_be_bytes())?;
+        self.set_tip(&block.get_prev_hash())
+    }
 
-                    match utxos.get_mut(&tx.id) {
-                        Some(v) => {
-                            v.outputs.push(tx.vout[index].clone());
-                        }
-                        None => {
-                            utxos.insert(
-                                tx.id.clone(), 
-                                TXOutputs {
-                                    outputs: vec![tx.vout[index].clone()],
-                                },
-                            );
-                        }
-                    }
-                }
+    fn set_tip(&mut self, block_hash: &str) -> Result<()> {
+        self.db.insert("LAST", block_hash.as_bytes())?;
+        self.db.flush()?;
+        self.current_hash = block_hash.to_string();
+        Ok(())
+    }
 
-                if !tx.is_coinbase() {
-                    for i in &tx.vin {
-                        match spent_txos.get_mut(&i.txid) {
-                            Some(v) => {
-                                v.push(i.vout);
-                            }
-                            None => {
-                                spent_txos.insert(i.txid.clone(), vec![i.vout]);
-                            }
-                        }
-                    }
+    pub fn txindex_enabled(&self) -> Result<bool> {
+        Ok(self.db.get("TXINDEX")?.is_some())
+    }
+
+    /// EnableTxindex builds the transaction index from the active chain and
+    /// keeps it up to date from then on
+    pub fn enable_txindex(&self) -> Result<()> {
+        self.db.drop_tree(TXINDEX_TREE)?;
+        let txindex = self.db.open_tree(TXINDEX_TREE)?;
+        for block in self.iter() {
+            index_transactions(&txindex, &block)?;
+        }
+        self.db.insert("TXINDEX", "1")?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    /// FindFork returns the last block shared by the branches ending at a and b
+    pub fn find_fork(&self, a: &str, b: &str) -> Result<String> {
+        let mut a = self.get_block(a)?;
+        let mut b = self.get_block(b)?;
+        while a.get_hash() != b.get_hash() {
+            if a.get_height() >= b.get_height() {
+                a = self.get_block(&a.get_prev_hash())?;
+            } else {
+                b = self.get_block(&b.get_prev_hash())?;
+            }
+        }
+        Ok(a.get_hash())
+    }
+
+    /// GetBranch returns the blocks from tip back to ancestor, newest first
+    /// and without the ancestor itself
+    pub fn get_branch(&self, tip: &str, ancestor: &str) -> Result<Vec<Block>> {
+        let mut branch = Vec::new();
+        let mut hash = tip.to_string();
+        while hash != ancestor {
+            let block = self.get_block(&hash)?;
+            hash = block.get_prev_hash();
+            branch.push(block);
+        }
+        Ok(branch)
+    }
+
+    pub fn has_block(&self, block_hash: &str) -> Result<bool> {
+        Ok(self.db.get(block_hash)?.is_some())
+    }
+
+    pub fn get_tip_hash(&self) -> String {
+        self.current_hash.clone()
+    }
+
+    /// CheckBlock validates the header and coinbase of a block against its parent
+    pub fn check_block(&self, block: &Block) -> Result<()> {
+        if !block.check_proof_of_work(self.config.network().pow_limit_bits())? {
+            return Err(BlockError::InvalidProofOfWork.into());
+        }
+        if !block.check_merkle_root()? {
+            return Err(BlockError::BadMerkleRoot.into());
+        }
+
+        if !self.has_block(&block.get_prev_hash())? {
+            return Err(BlockError::UnknownParent(block.get_prev_hash()).into());
+        }
+        let parent = self.get_block(&block.get_prev_hash())?;
+        if block.get_height() != parent.get_height() + 1 {
+            return Err(BlockError::BadHeight {
+                expected: parent.get_height() + 1,
+                found: block.get_height(),
+            }
+            .into());
+        }
+
+        let bits = self.get_next_bits(&parent.get_hash())?;
+        if block.get_bits() != bits {
+            return Err(BlockError::BadDifficulty {
+                expected: bits,
+                found: block.get_bits(),
+            }
+            .into());
+        }
+
+        if block.get_timestamp() <= self.get_median_time_past(&parent.get_hash())? {
+            return Err(BlockError::TimeTooOld.into());
+        }
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_millis();
+        if block.get_timestamp() > now + MAX_FUTURE_BLOCK_TIME {
+            return Err(BlockError::TimeTooNew.into());
+        }
+
+        let txs = block.get_transaction();
+        match txs.first() {
+            Some(cbtx) if cbtx.is_coinbase() => {
+                if cbtx.coinbase_height() != Some(block.get_height()) {
+                    return Err(BlockError::BadCoinbaseHeight.into());
                 }
             }
+            _ => return Err(BlockError::MissingCoinbase.into()),
+        }
+        if txs.iter().skip(1).any(|tx| tx.is_coinbase()) {
+            return Err(BlockError::MultipleCoinbase.into());
+        }
+        for tx in txs {
+            if tx.vin.is_empty() || tx.vout.is_empty() {
+                return Err(BlockError::EmptyTransaction(tx.id.clone()).into());
+            }
+            if tx.hash()? != tx.id {
+                return Err(BlockError::BadTransactionId(tx.id.clone()).into());
+            }
+            match Amount::checked_sum(tx.vout.iter().map(|out| out.value)) {
+                Some(total) if monetary::money_range(total, self.config.network()) => {}
+                _ => return Err(BlockError::ValueOutOfRange(tx.id.clone()).into()),
+            }
         }
-        utxos
+        Ok(())
+    }
+
+    /// GetMedianTimePast returns the median timestamp of the last blocks ending at block_hash
+    pub fn get_median_time_past(&self, block_hash: &str) -> Result<u128> {
+        let mut times = Vec::new();
+        let mut hash = block_hash.to_string();
+        while times.len() < MEDIAN_TIME_SPAN && !hash.is_empty() {
+            let block = self.get_block(&hash)?;
+            times.push(block.get_timestamp());
+            hash = block.get_prev_hash();
+        }
+        times.sort();
+        Ok(times[times.len() / 2])
+    }
+    
+    /// GetNextBits returns the difficulty target required for a block built on top of prev_hash
+    pub fn get_next_bits(&self, prev_hash: &str) -> Result<u32> {
+        let prev = self.get_block(prev_hash)?;
+        let height = prev.get_height() + 1;
+        if self.config.network().pow_no_retargeting() || height % pow::RETARGET_INTERVAL != 0 {
+            return Ok(prev.get_bits());
+        }
+
+        let mut first = prev.clone();
+        for _ in 0..pow::RETARGET_INTERVAL - 1 {
+            first = self.get_block(&first.get_prev_hash())?;
+        }
+        let actual_timespan = prev.get_timestamp().saturating_sub(first.get_timestamp());
+        pow::calculate_next_bits(prev.get_bits(), actual_timespan, self.config.network().pow_limit_bits())
     }
 
     /// FindTransactions finds a transaction by its ID
     pub fn find_transaction(&self, id: &str) -> Result<Transaction> {
+        match self.find_transaction_location(id)? {
+            Some(location) => {
+                let block = self.get_block(&location.block_hash)?;
+                Ok(block.get_transaction()[location.position as usize].clone())
+            }
+            None => Err(format_err!("Transaction is not found")),
+        }
+    }
+
+    /// FindDataTransactions returns the transactions of the active chain with
+    /// a data output starting with prefix, newest first, with the height of
+    /// their block
+    pub fn find_data_transactions(&self, prefix: &[u8]) -> Result<Vec<(i32, Transaction)>> {
+        let mut found = Vec::new();
+        for block in self.iter() {
+            for tx in block.get_transaction() {
+                let carries_prefix = tx
+                    .vout
+                    .iter()
+                    .any(|out| out.script_pubkey.data().is_some_and(|data| data.starts_with(prefix)));
+                if carries_prefix {
+                    found.push((block.get_height(), tx.clone()));
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// FindTransactionLocation finds the block of the active chain containing
+    /// a transaction, using the transaction index when it is enabled
+    pub fn find_transaction_location(&self, id: &str) -> Result<Option<TxLocation>> {
+        if self.txindex_enabled()? {
+            return match self.db.open_tree(TXINDEX_TREE)?.get(id.as_bytes())? {
+                Some(data) => Ok(Some(bincode::deserialize(&data)?)),
+                None => Ok(None),
+            };
+        }
         for b in self.iter() {
-            for tx in b.get_transaction() {
+            for (position, tx) in b.get_transaction().iter().enumerate() {
                 if tx.id == id {
-                    return Ok(tx.clone());
+                    return Ok(Some(TxLocation {
+                        block_hash: b.get_hash(),
+                        position: position as u32,
+                    }));
                 }
             }
         }
-        Err(format_err!("Transaction is not found"))
+        Ok(None)
     }
 
     /// SignTransaction signs inputs of a Transaction
-    pub fn sign_transaction(&self, tx: &mut Transaction, private_key: &[u8]) -> Result<()> {
+    pub fn sign_transaction(&self, tx: &mut Transaction, wallet: &Wallet, sighash_type: u8) -> Result<()> {
         let prev_txs = self.get_prev_txs(tx)?;
-        tx.sign(private_key, prev_txs)?;
+        tx.sign(wallet, prev_txs, sighash_type, self.config.network())?;
         Ok(())
     }
 
-    /// VerifyTransaction verifies transaction input signatures
+    /// VerifyTransaction verifies transaction input signatures, looking up the
+    /// spent outputs in the chain. Validation uses the UTXO set instead
     pub fn verify_transaction(&self, tx: &Transaction) -> Result<bool> {
-        let prev_txs = self.get_prev_txs(tx)?;
-        tx.verify(prev_txs)
+        if tx.is_coinbase() {
+            return Ok(true);
+        }
+        let prev_outputs = tx.prev_outputs(&self.get_prev_txs(tx)?)?;
+        tx.verify(&prev_outputs, self.config.network())
     }
 
-    pub fn iter(&self) -> BlockchainIter {
+    pub fn iter(&self) -> BlockchainIter<'_> {
         BlockchainIter {
             current_hash: self.current_hash.clone(),
-            bc: &self,
+            bc: self,
+        }
+    }
+
+    /// IterRange walks the active chain forward from start to end, both included
+    pub fn iter_range(&self, start: i32, end: i32) -> BlockchainRangeIter<'_> {
+        BlockchainRangeIter {
+            next_height: start.max(0),
+            end_height: end,
+            bc: self,
         }
     }
 }
 
+fn index_transactions(txindex: &sled::Tree, block: &Block) -> Result<()> {
+    for (position, tx) in block.get_transaction().iter().enumerate() {
+        let location = TxLocation {
+            block_hash: block.get_hash(),
+            position: position as u32,
+        };
+        txindex.insert(tx.id.as_bytes(), bincode::serialize(&location)?)?;
+    }
+    Ok(())
+}
+
 impl<'a> Iterator for BlockchainIter<'a> {
     type Item = Block;
 
@@ -232,3 +563,20 @@ impl<'a> Iterator for BlockchainIter<'a> {
         None
     }
 }
+
+impl<'a> Iterator for BlockchainRangeIter<'a> {
+    type Item = Block;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next_height > self.end_height {
+            return None;
+        }
+        match self.bc.get_block_by_height(self.next_height) {
+            Ok(Some(block)) => {
+                self.next_height += 1;
+                Some(block)
+            }
+            _ => None,
+        }
+    }
+}
@@ -1,14 +1,23 @@
 use std::process::exit;
 use std::vec;
 
-use bitcoincash_addr::Address;
-use clap::{Arg, Command};
+use clap::{Arg, ArgAction, Command};
+use failure::format_err;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde_json::json;
+use crate::amount::Amount;
 use crate::blockchain::Blockchain;
+use crate::config::{Config, Network};
 use crate::errors::Result;
+use crate::monetary;
+use crate::psbt::PartiallySignedTransaction;
+use crate::script::{self, Htlc, Script};
 use crate::server::Server;
-use crate::transaction::Transaction;
+use crate::transaction::{sighash_from_name, Transaction, TxOptions, SEQUENCE_FINAL, SEQUENCE_LOCKTIME_MASK};
 use crate::utxoset::UTXOSet;
-use crate::wallet::Wallets;
+use crate::tx::{TXInput, TXOutput};
+use crate::wallet::{encode_address, hash_pub_key, Wallets};
 
 pub struct Cli {}
 
@@ -22,6 +31,25 @@ impl Cli {
             .version("0.1")
             .author("cruzarciniega.d@gmail.com")
             .about("Blockchain in rust: a simple blockchain for learning")
+            .arg(Arg::new("datadir")
+                .long("datadir")
+                .global(true)
+                .default_value("data")
+                .help("Directory holding the blocks, UTXO set and wallets"))
+            .arg(Arg::new("network")
+                .long("network")
+                .global(true)
+                .value_parser(["main", "test", "regtest"])
+                .default_value("main")
+                .help("Network to use"))
+            .arg(Arg::new("datacarriersize")
+                .long("datacarriersize")
+                .global(true)
+                .help("Maximum number of bytes a data output of a relayed transaction may carry"))
+            .arg(Arg::new("coinbasematurity")
+                .long("coinbasematurity")
+                .global(true)
+                .help("Number of blocks a coinbase output must be buried under before it can be spent, the network default if not given"))
             .subcommand(
                 Command::new("create")
                     .about("Creates a new blockchain")
@@ -45,14 +73,30 @@ impl Cli {
                     .required(true))
                     .arg(Arg::new("AMOUNT")
                     .required(true))
-                    .arg(Arg::new("--mine")
-                    .required(false)),
+                    .arg(Arg::new("fee")
+                    .long("fee")
+                    .default_value("0")
+                    .help("Fee paid to the miner of the transaction, in coins"))
+                    .arg(Arg::new("locktime")
+                    .long("locktime")
+                    .default_value("0")
+                    .help("Height, or unix time from 500000000 on, the transaction cannot be mined before"))
+                    .arg(Arg::new("relative-locktime")
+                    .long("relative-locktime")
+                    .help("Number of blocks the spent outputs must be buried under before the transaction is mined"))
+                    .arg(Arg::new("data")
+                    .long("data")
+                    .help("Hex data to anchor in an unspendable output"))
+                    .arg(Arg::new("mine")
+                    .long("mine")
+                    .help("Mine the transaction immediately on this node")
+                    .action(ArgAction::SetTrue)),
             )
             .subcommand(
                 Command::new("startnode")
                     .about("Start the node server")
                     .arg(Arg::new("PORT")
-                    .required(true)),
+                    .help("Port to listen on, the default port of the network if not given")),
             )
             .subcommand(
                 Command::new("startminer")
@@ -63,22 +107,299 @@ impl Cli {
                     .required(true)),
 
             )
-            .subcommand(Command::new("reindex").about("Rebuilds the UTXO set"))
+            .subcommand(
+                Command::new("reindex")
+                    .about("Rebuilds the UTXO set")
+                    .arg(Arg::new("txindex")
+                    .long("txindex")
+                    .help("Also build the transaction index and keep it up to date")
+                    .action(ArgAction::SetTrue)),
+            )
+            .subcommand(
+                Command::new("gettransaction")
+                    .about("Get a transaction of the active chain and its block")
+                    .arg(Arg::new("TXID")
+                    .required(true)),
+            )
+            .subcommand(
+                Command::new("generate")
+                    .about("Mine blocks instantly on regtest")
+                    .arg(Arg::new("N")
+                    .help("Number of blocks to mine")
+                    .required(true))
+                    .arg(Arg::new("ADDRESS")
+                    .help("Address to receive the rewards, a new wallet if not given")),
+            )
+            .subcommand(
+                Command::new("getsupply")
+                    .about("Get the coins issued up to a height and the maximum supply")
+                    .arg(Arg::new("HEIGHT")
+                    .help("Height to report, the best height if not given")),
+            )
+            .subcommand(
+                Command::new("getblockhash")
+                    .about("Get the hash of the block at a height of the active chain")
+                    .arg(Arg::new("HEIGHT")
+                    .required(true)),
+            )
+            .subcommand(
+                Command::new("getblock")
+                    .about("Get the block at a height of the active chain")
+                    .arg(Arg::new("HEIGHT")
+                    .required(true))
+                    .arg(Arg::new("END")
+                    .help("Print every block from HEIGHT up to END")),
+            )
+            .subcommand(
+                Command::new("getpubkey")
+                    .about("Get the public key of a wallet address")
+                    .arg(Arg::new("ADDRESS")
+                    .required(true)),
+            )
+            .subcommand(
+                Command::new("createmultisig")
+                    .about("Create an address requiring M signatures of the public keys")
+                    .arg(Arg::new("M")
+                    .required(true))
+                    .arg(Arg::new("PUBKEY")
+                    .help("Hex public keys of the signers")
+                    .required(true)
+                    .num_args(1..)),
+            )
+            .subcommand(
+                Command::new("spendmultisig")
+                    .about("Create a transaction from a multisig address for its signers to sign")
+                    .arg(Arg::new("FROM")
+                    .required(true))
+                    .arg(Arg::new("TO")
+                    .required(true))
+                    .arg(Arg::new("AMOUNT")
+                    .required(true))
+                    .arg(Arg::new("fee")
+                    .long("fee")
+                    .default_value("0")
+                    .help("Fee paid to the miner of the transaction, in coins")),
+            )
+            .subcommand(
+                Command::new("signmultisig")
+                    .about("Add the signature of a wallet to a multisig transaction, sending it once complete")
+                    .arg(Arg::new("TRANSACTION")
+                    .help("Hex transaction printed by spendmultisig or signmultisig")
+                    .required(true))
+                    .arg(Arg::new("SIGNER")
+                    .help("Wallet address signing the transaction")
+                    .required(true))
+                    .arg(Arg::new("sighash")
+                    .long("sighash")
+                    .default_value("ALL")
+                    .help("What the signature commits to: ALL, NONE or SINGLE, optionally followed by |ANYONECANPAY"))
+                    .arg(Arg::new("mine")
+                    .long("mine")
+                    .help("Mine the complete transaction immediately on this node, rewarding the signer")
+                    .action(ArgAction::SetTrue)),
+            )
+            .subcommand(
+                Command::new("createhtlc")
+                    .about("Lock coins in a hash time-locked contract, refundable to FROM after LOCKTIME")
+                    .arg(Arg::new("FROM")
+                    .required(true))
+                    .arg(Arg::new("RECIPIENT")
+                    .help("Address that may spend the coins by revealing the secret")
+                    .required(true))
+                    .arg(Arg::new("AMOUNT")
+                    .required(true))
+                    .arg(Arg::new("LOCKTIME")
+                    .help("Height, or unix time from 500000000 on, after which FROM may take the coins back")
+                    .required(true))
+                    .arg(Arg::new("hash")
+                    .long("hash")
+                    .help("Hex SHA-256 hash of the secret, a new secret is made if not given"))
+                    .arg(Arg::new("fee")
+                    .long("fee")
+                    .default_value("0")
+                    .help("Fee paid to the miner of the transaction, in coins"))
+                    .arg(Arg::new("mine")
+                    .long("mine")
+                    .help("Mine the transaction immediately on this node")
+                    .action(ArgAction::SetTrue)),
+            )
+            .subcommand(
+                Command::new("redeemhtlc")
+                    .about("Spend the coins of an HTLC to its recipient by revealing the secret")
+                    .arg(Arg::new("SCRIPT")
+                    .help("Hex redeem script printed by createhtlc")
+                    .required(true))
+                    .arg(Arg::new("SECRET")
+                    .help("Hex secret whose SHA-256 hash the HTLC commits to")
+                    .required(true))
+                    .arg(Arg::new("fee")
+                    .long("fee")
+                    .default_value("0")
+                    .help("Fee paid to the miner of the transaction, in coins"))
+                    .arg(Arg::new("mine")
+                    .long("mine")
+                    .help("Mine the transaction immediately on this node")
+                    .action(ArgAction::SetTrue)),
+            )
+            .subcommand(
+                Command::new("refundhtlc")
+                    .about("Take the coins of an HTLC back once its lock time has passed")
+                    .arg(Arg::new("SCRIPT")
+                    .help("Hex redeem script printed by createhtlc")
+                    .required(true))
+                    .arg(Arg::new("fee")
+                    .long("fee")
+                    .default_value("0")
+                    .help("Fee paid to the miner of the transaction, in coins"))
+                    .arg(Arg::new("mine")
+                    .long("mine")
+                    .help("Mine the transaction immediately on this node")
+                    .action(ArgAction::SetTrue)),
+            )
+            .subcommand(
+                Command::new("findhtlcsecret")
+                    .about("Find the secret revealed by the redeem of an HTLC in the active chain")
+                    .arg(Arg::new("SCRIPT")
+                    .help("Hex redeem script printed by createhtlc")
+                    .required(true)),
+            )
+            .subcommand(
+                Command::new("finddata")
+                    .about("Find the transactions of the active chain anchoring data starting with a prefix")
+                    .arg(Arg::new("PREFIX")
+                    .help("Hex prefix of the data")
+                    .required(true)),
+            )
+            .subcommand(
+                Command::new("createpsbt")
+                    .about("Create a partially signed transaction paying AMOUNT from FROM, without its keys")
+                    .arg(Arg::new("FROM")
+                    .help("Address to spend from, a wallet or a multisig known to this node")
+                    .required(true))
+                    .arg(Arg::new("TO")
+                    .required(true))
+                    .arg(Arg::new("AMOUNT")
+                    .required(true))
+                    .arg(Arg::new("fee")
+                    .long("fee")
+                    .default_value("0")
+                    .help("Fee paid to the miner of the transaction, in coins")),
+            )
+            .subcommand(
+                Command::new("signpsbt")
+                    .about("Sign the inputs of a partially signed transaction a wallet can sign, offline")
+                    .arg(Arg::new("PSBT")
+                    .required(true))
+                    .arg(Arg::new("SIGNER")
+                    .help("Wallet address signing the transaction")
+                    .required(true))
+                    .arg(Arg::new("sighash")
+                    .long("sighash")
+                    .default_value("ALL")
+                    .help("What the signatures commit to: ALL, NONE or SINGLE, optionally followed by |ANYONECANPAY")),
+            )
+            .subcommand(
+                Command::new("combinepsbt")
+                    .about("Merge the signatures of copies of a partially signed transaction")
+                    .arg(Arg::new("PSBT")
+                    .required(true)
+                    .num_args(1..)),
+            )
+            .subcommand(
+                Command::new("finalizepsbt")
+                    .about("Build the signed transaction of a fully signed PSBT")
+                    .arg(Arg::new("PSBT")
+                    .required(true)),
+            )
+            .subcommand(
+                Command::new("broadcastpsbt")
+                    .about("Finalize a fully signed PSBT and send its transaction")
+                    .arg(Arg::new("PSBT")
+                    .required(true))
+                    .arg(Arg::new("mine")
+                    .long("mine")
+                    .value_name("ADDRESS")
+                    .help("Mine the transaction immediately on this node, rewarding the address")),
+            )
+            .subcommand(
+                Command::new("createrawtransaction")
+                    .about("Create an unsigned transaction spending the inputs and paying the outputs")
+                    .arg(Arg::new("input")
+                    .long("input")
+                    .value_name("TXID:VOUT")
+                    .help("Output to spend")
+                    .required(true)
+                    .action(ArgAction::Append))
+                    .arg(Arg::new("output")
+                    .long("output")
+                    .value_name("ADDRESS=AMOUNT")
+                    .help("Coins to pay to an address")
+                    .action(ArgAction::Append))
+                    .arg(Arg::new("data")
+                    .long("data")
+                    .help("Hex data to anchor in an unspendable output"))
+                    .arg(Arg::new("locktime")
+                    .long("locktime")
+                    .default_value("0")
+                    .help("Height, or unix time from 500000000 on, the transaction cannot be mined before")),
+            )
+            .subcommand(
+                Command::new("decoderawtransaction")
+                    .about("Print a raw transaction as JSON")
+                    .arg(Arg::new("HEX")
+                    .required(true)),
+            )
+            .subcommand(
+                Command::new("signrawtransaction")
+                    .about("Sign the inputs of a raw transaction the wallets can sign")
+                    .arg(Arg::new("HEX")
+                    .required(true))
+                    .arg(Arg::new("sighash")
+                    .long("sighash")
+                    .default_value("ALL")
+                    .help("What the signatures commit to: ALL, NONE or SINGLE, optionally followed by |ANYONECANPAY")),
+            )
+            .subcommand(
+                Command::new("sendrawtransaction")
+                    .about("Validate a signed raw transaction and send it")
+                    .arg(Arg::new("HEX")
+                    .required(true))
+                    .arg(Arg::new("mine")
+                    .long("mine")
+                    .value_name("ADDRESS")
+                    .help("Mine the transaction immediately on this node, rewarding the address")),
+            )
             .subcommand(Command::new("printchain").about("Print all the blocks of the blockchain"))
             .subcommand(Command::new("createwallet").about("Create a new wallet"))
             .subcommand(Command::new("listaddresses").about("List all addresses"))
             .get_matches();
-        
-        if let Some(ref matches) = matches.subcommand_matches("startnode") {
-            if let Some(port) = matches.get_one::<String>("PORT") {
-                let bc = Blockchain::new()?;
-                let utxo_set = UTXOSet { blockchain: bc };
-                let server = Server::new(port, "", utxo_set)?;
-                server.start_server()?;
+
+        let network = Network::from_name(matches.get_one::<String>("network").unwrap())?;
+        let mut config = Config::new(matches.get_one::<String>("datadir").unwrap(), network);
+        if let Some(size) = matches.get_one::<String>("datacarriersize") {
+            config = config.with_data_carrier_size(size.parse()?);
+        }
+        if let Some(depth) = matches.get_one::<String>("coinbasematurity") {
+            let depth: i32 = depth.parse()?;
+            if depth < 0 {
+                println!("coinbasematurity must not be negative");
+                exit(1)
             }
+            config = config.with_coinbase_maturity(depth);
+        }
+        
+        if let Some(matches) = matches.subcommand_matches("startnode") {
+            let port = match matches.get_one::<String>("PORT") {
+                Some(port) => port.clone(),
+                None => network.default_port().to_string(),
+            };
+            let bc = Blockchain::new(&config)?;
+            let utxo_set = UTXOSet::new(bc)?;
+            let server = Server::new(&port, "", utxo_set)?;
+            server.start_server()?;
         }
 
-        if let Some(ref matches) = matches.subcommand_matches("startminer") {
+        if let Some(matches) = matches.subcommand_matches("startminer") {
             let port = if let Some(port) = matches.get_one::<String>("PORT") {
                 port
             } else {
@@ -92,42 +413,39 @@ impl Cli {
                 println!("ADDRESS not supply!: usage");
                 exit(1)
             };
-            let bc = Blockchain::new()?;
-            let utxo_set = UTXOSet { blockchain: bc };
+            Wallets::new(&config)?.decode_address(address)?;
+            let bc = Blockchain::new(&config)?;
+            let utxo_set = UTXOSet::new(bc)?;
             let server = Server::new(port, address, utxo_set)?;
             server.start_server()?;
         }
 
-        if let Some(ref matches) = matches.subcommand_matches("create") {
+        if let Some(matches) = matches.subcommand_matches("create") {
             if let Some(address) = matches.get_one::<String>("ADDRESS") {
+                Wallets::new(&config)?.decode_address(address)?;
                 let address = String::from(address);
-                let bc = Blockchain::create_blockchain(address.clone())?;
-                let utxo_set = UTXOSet { blockchain: bc };
+                let bc = Blockchain::create_blockchain(&config, address.clone())?;
+                let utxo_set = UTXOSet::new(bc)?;
                 utxo_set.reindex()?;
-                print!("create blockchain \n")
+                println!("create blockchain")
             }
             /*else {
                 print!("Not printing testing lists...");
             }*/
         }
     
-        if let Some(ref matches) = matches.subcommand_matches("getbalance") {
+        if let Some(matches) = matches.subcommand_matches("getbalance") {
             if let Some(address) = matches.get_one::<String>("ADDRESS") {
-                let pub_key_hash = Address::decode(address).unwrap().body;
-                let bc = Blockchain::new()?;
-                // let utxos = bc.find_utxo(&pub_key_hash);
-                let utxo_set = UTXOSet { blockchain: bc };
-                let utxos = utxo_set.find_utxo(&pub_key_hash)?;
-                let mut balance = 0;
-                
-                for out in utxos.outputs {
-                    balance += out.value;
-                }
-                println!("Balance of '{}': {}", address, balance);
+                let script_pubkey = Wallets::new(&config)?.decode_address(address)?;
+                let bc = Blockchain::new(&config)?;
+                let utxo_set = UTXOSet::new(bc)?;
+                let (spendable, immature) = utxo_set.get_balance(&script_pubkey)?;
+                println!("Balance of '{}': {}", address, spendable);
+                println!("Immature balance of '{}': {}", address, immature);
             }
         }
     
-        if let Some(ref matches) = matches.subcommand_matches("send") {
+        if let Some(matches) = matches.subcommand_matches("send") {
             let from = if let Some(address) = matches.get_one::<String>("FROM") {
                 address
             } else {
@@ -142,72 +460,674 @@ impl Cli {
                 exit(1)
             };
 
-            let amount: i32 = if let Some(amount) = matches.get_one::<String>("AMOUNT") {
+            let amount: Amount = if let Some(amount) = matches.get_one::<String>("AMOUNT") {
                 amount.parse()?
             } else {
                 println!("from not supply!: usage");
                 exit(1)
             };
 
-            if matches.contains_id("mine") {
-                cmd_send(from, to, amount, true)?;
-            } else {
-                cmd_send(from, to, amount, false)?;
+            let fee: Amount = matches.get_one::<String>("fee").unwrap().parse()?;
+            let lock_time: u32 = matches.get_one::<String>("locktime").unwrap().parse()?;
+            let sequence = match matches.get_one::<String>("relative-locktime") {
+                Some(blocks) => {
+                    let blocks: u32 = blocks.parse()?;
+                    if blocks > SEQUENCE_LOCKTIME_MASK {
+                        println!("relative-locktime must not exceed {}", SEQUENCE_LOCKTIME_MASK);
+                        exit(1)
+                    }
+                    blocks
+                }
+                // any other sequence enforces the lock time
+                None if lock_time != 0 => SEQUENCE_FINAL - 1,
+                None => SEQUENCE_FINAL,
+            };
+
+            let data = match matches.get_one::<String>("data") {
+                Some(data) => Some(hex::decode(data)?),
+                None => None,
+            };
+            if data.as_ref().is_some_and(|data| data.len() > config.data_carrier_size()) {
+                println!("data must not exceed {} bytes", config.data_carrier_size());
+                exit(1)
             }
+            let options = TxOptions {
+                fee,
+                lock_time,
+                sequence,
+                data,
+            };
+
+            cmd_send(&config, from, to, amount, &options, matches.get_flag("mine"))?;
         }
 
-        if let Some(_) = matches.subcommand_matches("reindex") {
-            let bc = Blockchain::new()?;
-            let utxo_set = UTXOSet { blockchain: bc };
+        if let Some(matches) = matches.subcommand_matches("reindex") {
+            let bc = Blockchain::new(&config)?;
+            let utxo_set = UTXOSet::new(bc)?;
             utxo_set.reindex()?;
+            if matches.get_flag("txindex") {
+                utxo_set.blockchain.enable_txindex()?;
+            }
             let count = utxo_set.count_transactions()?;
             print!("Done! There are {} transactions in the UTXO set.", count);
         }
+
+        if let Some(matches) = matches.subcommand_matches("gettransaction") {
+            if let Some(txid) = matches.get_one::<String>("TXID") {
+                cmd_get_transaction(&config, txid)?;
+            }
+        }
+
+        if let Some(matches) = matches.subcommand_matches("generate") {
+            let count: i32 = if let Some(count) = matches.get_one::<String>("N") {
+                count.parse()?
+            } else {
+                println!("N not supply!: usage");
+                exit(1)
+            };
+            cmd_generate(&config, count, matches.get_one::<String>("ADDRESS"))?;
+        }
+
+        if let Some(matches) = matches.subcommand_matches("getsupply") {
+            let height = match matches.get_one::<String>("HEIGHT") {
+                Some(height) => height.parse()?,
+                None => Blockchain::new(&config)?.get_best_height()?,
+            };
+            println!("Height: {}", height);
+            println!("Block subsidy: {}", monetary::block_subsidy(height, network));
+            println!("Issued supply: {}", monetary::issued_supply(height, network));
+            println!("Max supply: {}", monetary::max_supply(network));
+        }
+
+        if let Some(matches) = matches.subcommand_matches("getblockhash") {
+            if let Some(height) = matches.get_one::<String>("HEIGHT") {
+                let bc = Blockchain::new(&config)?;
+                match bc.get_block_hash(height.parse()?)? {
+                    Some(hash) => println!("{}", hash),
+                    None => {
+                        println!("There is no block at height {}", height);
+                        exit(1)
+                    }
+                }
+            }
+        }
+
+        if let Some(matches) = matches.subcommand_matches("getblock") {
+            if let Some(height) = matches.get_one::<String>("HEIGHT") {
+                let bc = Blockchain::new(&config)?;
+                if let Some(end) = matches.get_one::<String>("END") {
+                    for block in bc.iter_range(height.parse()?, end.parse()?) {
+                        println!("{:#?}", block);
+                    }
+                } else {
+                    match bc.get_block_by_height(height.parse()?)? {
+                        Some(block) => println!("{:#?}", block),
+                        None => {
+                            println!("There is no block at height {}", height);
+                            exit(1)
+                        }
+                    }
+                }
+            }
+        }
         
-        if let Some(ref _matches) = matches.subcommand_matches("printchain") {
-            cmd_print_chain()?;
+        if matches.subcommand_matches("printchain").is_some() {
+            cmd_print_chain(&config)?;
         }
 
-        if let Some(_) = matches.subcommand_matches("createwallet") {
-            let mut ws = Wallets::new()?;
+        if matches.subcommand_matches("createwallet").is_some() {
+            let mut ws = Wallets::new(&config)?;
             let address = ws.create_wallet();
             ws.save_all()?;
-            print!("Wallet created: {}\n", address);
+            println!("Wallet created: {}", address);
         }
 
-        if let Some(_) = matches.subcommand_matches("listaddresses") {
-            let ws = Wallets::new()?;
+        if matches.subcommand_matches("listaddresses").is_some() {
+            let ws = Wallets::new(&config)?;
             let addresses = ws.get_all_addresses();
             for address in addresses {
                 println!("{}", address);
             }
         }
 
+        if let Some(matches) = matches.subcommand_matches("getpubkey") {
+            if let Some(address) = matches.get_one::<String>("ADDRESS") {
+                let ws = Wallets::new(&config)?;
+                match ws.get_wallet(address) {
+                    Some(wallet) => println!("{}", hex::encode(&wallet.public_key)),
+                    None => {
+                        println!("Wallet {} not found", address);
+                        exit(1)
+                    }
+                }
+            }
+        }
+
+        if let Some(matches) = matches.subcommand_matches("createmultisig") {
+            let m: usize = matches.get_one::<String>("M").unwrap().parse()?;
+            let pub_keys = matches
+                .get_many::<String>("PUBKEY")
+                .unwrap()
+                .map(hex::decode)
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+            let mut ws = Wallets::new(&config)?;
+            let address = ws.add_multisig(m, &pub_keys)?;
+            ws.save_all()?;
+            println!("Multisig created: {}", address);
+            println!("Redeem script: {}", ws.get_script(&address).unwrap());
+        }
+
+        if let Some(matches) = matches.subcommand_matches("spendmultisig") {
+            let from = matches.get_one::<String>("FROM").unwrap();
+            let to = matches.get_one::<String>("TO").unwrap();
+            let amount: Amount = matches.get_one::<String>("AMOUNT").unwrap().parse()?;
+            let fee: Amount = matches.get_one::<String>("fee").unwrap().parse()?;
+            cmd_spend_multisig(&config, from, to, amount, fee)?;
+        }
+
+        if let Some(matches) = matches.subcommand_matches("createhtlc") {
+            let from = matches.get_one::<String>("FROM").unwrap();
+            let recipient = matches.get_one::<String>("RECIPIENT").unwrap();
+            let amount: Amount = matches.get_one::<String>("AMOUNT").unwrap().parse()?;
+            let lock_time: u32 = matches.get_one::<String>("LOCKTIME").unwrap().parse()?;
+            let hash = match matches.get_one::<String>("hash") {
+                Some(hash) => Some(hex::decode(hash)?),
+                None => None,
+            };
+            let fee: Amount = matches.get_one::<String>("fee").unwrap().parse()?;
+            cmd_create_htlc(&config, from, recipient, amount, lock_time, hash, fee, matches.get_flag("mine"))?;
+        }
+
+        if let Some(matches) = matches.subcommand_matches("redeemhtlc") {
+            let script = matches.get_one::<String>("SCRIPT").unwrap();
+            let secret = hex::decode(matches.get_one::<String>("SECRET").unwrap())?;
+            let fee: Amount = matches.get_one::<String>("fee").unwrap().parse()?;
+            cmd_spend_htlc(&config, script, Some(&secret), fee, matches.get_flag("mine"))?;
+        }
+
+        if let Some(matches) = matches.subcommand_matches("refundhtlc") {
+            let script = matches.get_one::<String>("SCRIPT").unwrap();
+            let fee: Amount = matches.get_one::<String>("fee").unwrap().parse()?;
+            cmd_spend_htlc(&config, script, None, fee, matches.get_flag("mine"))?;
+        }
+
+        if let Some(matches) = matches.subcommand_matches("findhtlcsecret") {
+            let script = matches.get_one::<String>("SCRIPT").unwrap();
+            cmd_find_htlc_secret(&config, script)?;
+        }
+
+        if let Some(matches) = matches.subcommand_matches("finddata") {
+            let prefix = hex::decode(matches.get_one::<String>("PREFIX").unwrap())?;
+            let bc = Blockchain::new(&config)?;
+            for (height, tx) in bc.find_data_transactions(&prefix)? {
+                let datas = tx.vout.iter().filter_map(|out| out.script_pubkey.data());
+                for data in datas.filter(|data| data.starts_with(&prefix)) {
+                    println!("{} {} {}", height, tx.id, hex::encode(data));
+                }
+            }
+        }
+
+        if let Some(matches) = matches.subcommand_matches("signmultisig") {
+            let tx = matches.get_one::<String>("TRANSACTION").unwrap();
+            let signer = matches.get_one::<String>("SIGNER").unwrap();
+            let sighash_type = sighash_from_name(matches.get_one::<String>("sighash").unwrap())?;
+            cmd_sign_multisig(&config, tx, signer, sighash_type, matches.get_flag("mine"))?;
+        }
+
+        if let Some(matches) = matches.subcommand_matches("createrawtransaction") {
+            let mut vin = Vec::new();
+            for input in matches.get_many::<String>("input").unwrap() {
+                let (txid, vout) = input
+                    .split_once(':')
+                    .ok_or_else(|| format_err!("Input {} is not TXID:VOUT", input))?;
+                vin.push(TXInput {
+                    txid: txid.to_string(),
+                    vout: vout.parse()?,
+                    script_sig: Script::default(),
+                    sequence: SEQUENCE_FINAL,
+                });
+            }
+            let wallets = Wallets::new(&config)?;
+            let mut vout = Vec::new();
+            for output in matches.get_many::<String>("output").into_iter().flatten() {
+                let (address, amount) = output
+                    .split_once('=')
+                    .ok_or_else(|| format_err!("Output {} is not ADDRESS=AMOUNT", output))?;
+                wallets.decode_address(address)?;
+                vout.push(TXOutput::new(amount.parse()?, address.to_string())?);
+            }
+            if let Some(data) = matches.get_one::<String>("data") {
+                vout.push(TXOutput::new_data(&hex::decode(data)?));
+            }
+            let lock_time: u32 = matches.get_one::<String>("locktime").unwrap().parse()?;
+            if lock_time != 0 {
+                // any other sequence enforces the lock time
+                for vin in &mut vin {
+                    vin.sequence = SEQUENCE_FINAL - 1;
+                }
+            }
+            let mut tx = Transaction {
+                id: String::new(),
+                vin,
+                vout,
+                lock_time,
+            };
+            tx.id = tx.hash()?;
+            println!("{}", tx.to_hex()?);
+        }
+
+        if let Some(matches) = matches.subcommand_matches("decoderawtransaction") {
+            let tx = Transaction::from_hex(matches.get_one::<String>("HEX").unwrap())?;
+            println!("{}", serde_json::to_string_pretty(&tx_to_json(&tx, network)?)?);
+        }
+
+        if let Some(matches) = matches.subcommand_matches("signrawtransaction") {
+            let tx = matches.get_one::<String>("HEX").unwrap();
+            let sighash_type = sighash_from_name(matches.get_one::<String>("sighash").unwrap())?;
+            cmd_sign_raw_transaction(&config, tx, sighash_type)?;
+        }
+
+        if let Some(matches) = matches.subcommand_matches("sendrawtransaction") {
+            let tx = Transaction::from_hex(matches.get_one::<String>("HEX").unwrap())?;
+            let miner = matches.get_one::<String>("mine");
+            cmd_send_raw_transaction(&config, tx, miner)?;
+        }
+
+        if let Some(matches) = matches.subcommand_matches("createpsbt") {
+            let from = matches.get_one::<String>("FROM").unwrap();
+            let to = matches.get_one::<String>("TO").unwrap();
+            let amount: Amount = matches.get_one::<String>("AMOUNT").unwrap().parse()?;
+            let fee: Amount = matches.get_one::<String>("fee").unwrap().parse()?;
+            cmd_create_psbt(&config, from, to, amount, fee)?;
+        }
+
+        if let Some(matches) = matches.subcommand_matches("signpsbt") {
+            let mut psbt = PartiallySignedTransaction::from_hex(matches.get_one::<String>("PSBT").unwrap())?;
+            let signer = matches.get_one::<String>("SIGNER").unwrap();
+            let sighash_type = sighash_from_name(matches.get_one::<String>("sighash").unwrap())?;
+            let wallets = Wallets::new(&config)?;
+            let wallet = match wallets.get_wallet(signer) {
+                Some(wallet) => wallet,
+                None => {
+                    println!("Wallet {} not found", signer);
+                    exit(1)
+                }
+            };
+            let signed = psbt.sign(wallet, sighash_type, network)?;
+            println!("Signed {} inputs", signed);
+            println!("{}", psbt.to_hex()?);
+        }
+
+        if let Some(matches) = matches.subcommand_matches("combinepsbt") {
+            let mut psbts = matches.get_many::<String>("PSBT").unwrap();
+            let mut psbt = PartiallySignedTransaction::from_hex(psbts.next().unwrap())?;
+            for other in psbts {
+                psbt.combine(PartiallySignedTransaction::from_hex(other)?)?;
+            }
+            println!("{}", psbt.to_hex()?);
+        }
+
+        if let Some(matches) = matches.subcommand_matches("finalizepsbt") {
+            let psbt = PartiallySignedTransaction::from_hex(matches.get_one::<String>("PSBT").unwrap())?;
+            println!("{}", psbt.finalize()?.to_hex()?);
+        }
+
+        if let Some(matches) = matches.subcommand_matches("broadcastpsbt") {
+            let psbt = PartiallySignedTransaction::from_hex(matches.get_one::<String>("PSBT").unwrap())?;
+            let miner = matches.get_one::<String>("mine");
+            cmd_send_raw_transaction(&config, psbt.finalize()?, miner)?;
+        }
+
         Ok(())
     }
 }
 
-fn cmd_send(from: &str, to: &str, amount: i32, mine_now: bool) -> Result<()> {
-    let bc = Blockchain::new()?;
-    let mut utxo_set = UTXOSet { blockchain: bc };
-    let wallets = Wallets::new()?;
+fn cmd_send(config: &Config, from: &str, to: &str, amount: Amount, options: &TxOptions, mine_now: bool) -> Result<()> {
+    let wallets = Wallets::new(config)?;
+    wallets.decode_address(to)?;
+    let bc = Blockchain::new(config)?;
+    let utxo_set = UTXOSet::new(bc)?;
     let wallet = wallets.get_wallet(from).unwrap();
-    let tx = Transaction::new_utxo(wallet, to, amount, &utxo_set)?;
+    let tx = Transaction::new_utxo(wallet, to, amount, options, &utxo_set)?;
+    submit_transaction(config, tx, from, utxo_set, mine_now)?;
+
+    println!("success!");
+    Ok(())
+}
+
+/// SubmitTransaction mines the transaction right away, rewarding the miner
+/// address, or sends it to the seed nodes
+fn submit_transaction(config: &Config, tx: Transaction, miner: &str, mut utxo_set: UTXOSet, mine_now: bool) -> Result<()> {
     if mine_now {
-        let cbtx = Transaction::new_coinbase(from.to_string(), String::from("reward!"))?;
+        let height = utxo_set.blockchain.get_best_height()? + 1;
+        let reward = monetary::block_subsidy(height, config.network())
+            .checked_add(utxo_set.validate_transaction(&tx)?)
+            .ok_or_else(|| format_err!("Block reward overflow"))?;
+        let cbtx = Transaction::new_coinbase(miner.to_string(), String::from("reward!"), height, reward)?;
         let new_block = utxo_set.blockchain.mine_block(vec![cbtx, tx])?;
 
         utxo_set.update(&new_block)?;
     } else {
         Server::send_transaction(&tx, utxo_set)?;
     }
+    Ok(())
+}
 
-    print!("success! \n");
+fn cmd_spend_multisig(config: &Config, from: &str, to: &str, amount: Amount, fee: Amount) -> Result<()> {
+    let wallets = Wallets::new(config)?;
+    wallets.decode_address(to)?;
+    let redeem_script = match wallets.get_script(from) {
+        Some(redeem_script) => redeem_script,
+        None => {
+            println!("Multisig {} not found, create it with createmultisig", from);
+            exit(1)
+        }
+    };
+    let bc = Blockchain::new(config)?;
+    let utxo_set = UTXOSet::new(bc)?;
+    let tx = Transaction::new_multisig(redeem_script, to, amount, fee, &utxo_set)?;
+    println!("{}", tx.to_hex()?);
+    Ok(())
+}
+
+fn cmd_sign_multisig(config: &Config, tx: &str, signer: &str, sighash_type: u8, mine_now: bool) -> Result<()> {
+    let mut tx = Transaction::from_hex(tx)?;
+    let wallets = Wallets::new(config)?;
+    let wallet = match wallets.get_wallet(signer) {
+        Some(wallet) => wallet,
+        None => {
+            println!("Wallet {} not found", signer);
+            exit(1)
+        }
+    };
+    let bc = Blockchain::new(config)?;
+    let utxo_set = UTXOSet::new(bc)?;
+    utxo_set.blockchain.sign_transaction(&mut tx, wallet, sighash_type)?;
+
+    if !utxo_set.blockchain.verify_transaction(&tx)? {
+        println!("Signatures are missing, pass the transaction to the next signer:");
+        println!("{}", tx.to_hex()?);
+        return Ok(());
+    }
+    let txid = tx.id.clone();
+    submit_transaction(config, tx, signer, utxo_set, mine_now)?;
+    println!("Transaction {} sent", txid);
+    Ok(())
+}
+
+/// TxToJson describes a transaction with its scripts in asm form
+fn tx_to_json(tx: &Transaction, network: Network) -> Result<serde_json::Value> {
+    let vin: Vec<serde_json::Value> = tx
+        .vin
+        .iter()
+        .map(|vin| {
+            json!({
+                "txid": vin.txid,
+                "vout": vin.vout,
+                "script_sig": vin.script_sig.to_string(),
+                "sequence": vin.sequence,
+            })
+        })
+        .collect();
+    let vout: Vec<serde_json::Value> = tx
+        .vout
+        .iter()
+        .enumerate()
+        .map(|(n, out)| {
+            json!({
+                "n": n,
+                "value": out.value.to_string(),
+                "script_pubkey": out.script_pubkey.to_string(),
+                "address": encode_address(&out.script_pubkey, network),
+            })
+        })
+        .collect();
+    Ok(json!({
+        "txid": tx.id,
+        "wtxid": tx.witness_hash()?,
+        "locktime": tx.lock_time,
+        "vin": vin,
+        "vout": vout,
+    }))
+}
+
+/// CmdSignRawTransaction signs the inputs of the transaction with every
+/// wallet. Inputs spending a known multisig get their signature slots first
+fn cmd_sign_raw_transaction(config: &Config, tx: &str, sighash_type: u8) -> Result<()> {
+    let mut tx = Transaction::from_hex(tx)?;
+    let wallets = Wallets::new(config)?;
+    let bc = Blockchain::new(config)?;
+    for vin in &mut tx.vin {
+        if !vin.script_sig.0.is_empty() {
+            continue;
+        }
+        let prev_tx = bc.find_transaction(&vin.txid)?;
+        let redeem_script = prev_tx
+            .vout
+            .get(vin.vout as usize)
+            .and_then(|out| out.script_pubkey.p2sh_hash())
+            .and_then(|script_hash| wallets.find_script(script_hash));
+        if let Some(redeem_script) = redeem_script {
+            if let Some((_, pub_keys)) = redeem_script.multisig_keys() {
+                vin.script_sig = Script::new_multisig_sig(&vec![Vec::new(); pub_keys.len()], redeem_script)?;
+            }
+        }
+    }
+    for wallet in wallets.get_wallets() {
+        bc.sign_transaction(&mut tx, wallet, sighash_type)?;
+    }
+    let complete = bc.verify_transaction(&tx)?;
+    println!("{}", serde_json::to_string_pretty(&json!({ "hex": tx.to_hex()?, "complete": complete }))?);
+    Ok(())
+}
+
+/// CmdSendRawTransaction validates a signed transaction against the UTXO set,
+/// then mines it rewarding the miner address or sends it to the seed nodes
+fn cmd_send_raw_transaction(config: &Config, tx: Transaction, miner: Option<&String>) -> Result<()> {
+    if let Some(miner) = miner {
+        Wallets::new(config)?.decode_address(miner)?;
+    }
+    let bc = Blockchain::new(config)?;
+    let utxo_set = UTXOSet::new(bc)?;
+    utxo_set.validate_transaction(&tx)?;
+    let txid = tx.id.clone();
+    match miner {
+        Some(miner) => submit_transaction(config, tx, miner, utxo_set, true)?,
+        None => submit_transaction(config, tx, "", utxo_set, false)?,
+    }
+    println!("{}", txid);
+    Ok(())
+}
+
+fn cmd_create_psbt(config: &Config, from: &str, to: &str, amount: Amount, fee: Amount) -> Result<()> {
+    let wallets = Wallets::new(config)?;
+    let from_script = wallets.decode_address(from)?;
+    wallets.decode_address(to)?;
+    let bc = Blockchain::new(config)?;
+    let utxo_set = UTXOSet::new(bc)?;
+    let tx = Transaction::new_unsigned(&from_script, from.to_string(), to, amount, fee, &utxo_set)?;
+    let psbt = PartiallySignedTransaction::new(tx, wallets.get_script(from), &utxo_set)?;
+    println!("{}", psbt.to_hex()?);
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn cmd_create_htlc(
+    config: &Config,
+    from: &str,
+    recipient: &str,
+    amount: Amount,
+    lock_time: u32,
+    hash: Option<Vec<u8>>,
+    fee: Amount,
+    mine_now: bool,
+) -> Result<()> {
+    let mut wallets = Wallets::new(config)?;
+    let recipient = match wallets.decode_address(recipient)?.p2pkh_hash() {
+        Some(pub_key_hash) => pub_key_hash.to_vec(),
+        None => {
+            println!("The recipient of an HTLC must be a wallet address");
+            exit(1)
+        }
+    };
+    let wallet = match wallets.get_wallet(from) {
+        Some(wallet) => wallet.clone(),
+        None => {
+            println!("Wallet {} not found", from);
+            exit(1)
+        }
+    };
+    let (hash, secret) = match hash {
+        Some(hash) if hash.len() == 32 => (hash, None),
+        Some(_) => {
+            println!("The hash must be 32 bytes long");
+            exit(1)
+        }
+        None => {
+            let mut secret = vec![0u8; 32];
+            OsRng.fill_bytes(&mut secret);
+            (script::sha256(&secret), Some(secret))
+        }
+    };
+    let mut refund = wallet.public_key.clone();
+    hash_pub_key(&mut refund);
+    let redeem_script = Script::new_htlc(&Htlc {
+        hash: hash.clone(),
+        recipient,
+        refund,
+        lock_time,
+    });
+    let address = wallets.add_script(redeem_script.clone())?;
+    wallets.save_all()?;
+
+    let bc = Blockchain::new(config)?;
+    let utxo_set = UTXOSet::new(bc)?;
+    let options = TxOptions {
+        fee,
+        ..TxOptions::default()
+    };
+    let tx = Transaction::new_utxo(&wallet, &address, amount, &options, &utxo_set)?;
+    submit_transaction(config, tx, from, utxo_set, mine_now)?;
+
+    println!("HTLC address: {}", address);
+    println!("Redeem script: {}", hex::encode(redeem_script.to_bytes()?));
+    println!("Hash: {}", hex::encode(&hash));
+    if let Some(secret) = secret {
+        println!("Secret: {}", hex::encode(secret));
+    }
+    Ok(())
+}
+
+/// SpendHTLC moves the coins of an HTLC to its recipient when the secret is
+/// given, or back to its refund key otherwise
+fn cmd_spend_htlc(config: &Config, script: &str, secret: Option<&[u8]>, fee: Amount, mine_now: bool) -> Result<()> {
+    let redeem_script = Script::from_bytes(&hex::decode(script)?)?;
+    let htlc = match redeem_script.htlc() {
+        Some(htlc) => htlc,
+        None => {
+            println!("{} is not an HTLC redeem script", redeem_script);
+            exit(1)
+        }
+    };
+    let pub_key_hash = if secret.is_some() { &htlc.recipient } else { &htlc.refund };
+    let wallets = Wallets::new(config)?;
+    let wallet = match wallets.find_wallet(pub_key_hash) {
+        Some(wallet) => wallet,
+        None => {
+            println!("No wallet may spend this HTLC");
+            exit(1)
+        }
+    };
+    let bc = Blockchain::new(config)?;
+    let utxo_set = UTXOSet::new(bc)?;
+    let tx = Transaction::new_htlc_spend(&redeem_script, wallet, secret, fee, &utxo_set)?;
+    let txid = tx.id.clone();
+    submit_transaction(config, tx, &wallet.get_address(config.network()), utxo_set, mine_now)?;
+    println!("Transaction {} sent", txid);
+    Ok(())
+}
+
+/// FindHTLCSecret looks for an input of the active chain redeeming the HTLC,
+/// which reveals the secret
+fn cmd_find_htlc_secret(config: &Config, script: &str) -> Result<()> {
+    let redeem_script = Script::from_bytes(&hex::decode(script)?)?;
+    let htlc = match redeem_script.htlc() {
+        Some(htlc) => htlc,
+        None => {
+            println!("{} is not an HTLC redeem script", redeem_script);
+            exit(1)
+        }
+    };
+    let bc = Blockchain::new(config)?;
+    for block in bc.iter() {
+        for tx in block.get_transaction() {
+            for vin in &tx.vin {
+                if let Some((script, pushes)) = vin.script_sig.redeem_script() {
+                    // sig, pub key, secret and the selector of the hash branch
+                    if script == redeem_script && pushes.len() == 4 && script::sha256(&pushes[2]) == htlc.hash {
+                        println!("Secret: {}", hex::encode(&pushes[2]));
+                        println!("Revealed by transaction {}", tx.id);
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+    println!("The HTLC has not been redeemed in the active chain");
+    exit(1)
+}
+
+fn cmd_generate(config: &Config, count: i32, address: Option<&String>) -> Result<()> {
+    if config.network() != Network::Regtest {
+        println!("generate is only available on regtest");
+        exit(1)
+    }
+    let address = match address {
+        Some(address) => {
+            Wallets::new(config)?.decode_address(address)?;
+            address.clone()
+        }
+        None => {
+            let mut ws = Wallets::new(config)?;
+            let address = ws.create_wallet();
+            ws.save_all()?;
+            address
+        }
+    };
+
+    let bc = Blockchain::new(config)?;
+    let mut utxo_set = UTXOSet::new(bc)?;
+    for _ in 0..count {
+        let height = utxo_set.blockchain.get_best_height()? + 1;
+        let reward = monetary::block_subsidy(height, config.network());
+        let cbtx = Transaction::new_coinbase(address.clone(), String::new(), height, reward)?;
+        let new_block = utxo_set.blockchain.mine_block(vec![cbtx])?;
+        utxo_set.update(&new_block)?;
+        println!("{}", new_block.get_hash());
+    }
+    Ok(())
+}
+
+fn cmd_get_transaction(config: &Config, txid: &str) -> Result<()> {
+    let bc = Blockchain::new(config)?;
+    let location = match bc.find_transaction_location(txid)? {
+        Some(location) => location,
+        None => {
+            println!("Transaction {} is not in the active chain", txid);
+            exit(1)
+        }
+    };
+    let block = bc.get_block(&location.block_hash)?;
+    let tx = &block.get_transaction()[location.position as usize];
+    println!("{:#?}", tx);
+    println!("Block: {}", block.get_hash());
+    println!("Height: {}", block.get_height());
+    println!("Confirmations: {}", bc.get_best_height()? - block.get_height() + 1);
     Ok(())
 }
 
-fn cmd_print_chain() -> Result<()> {
-    let bc = Blockchain::new()?;
+fn cmd_print_chain(config: &Config) -> Result<()> {
+    let bc = Blockchain::new(config)?;
     for b in bc.iter() {
         println!("{:#?}", b);
     }
@@ -0,0 +1,196 @@
+use std::path::PathBuf;
+
+use bitcoincash_addr::Network as AddressNetwork;
+use failure::format_err;
+
+use crate::errors::Result;
+use crate::pow;
+
+/// Default number of bytes a data output of a relayed transaction may carry
+pub const DEFAULT_DATA_CARRIER_SIZE: usize = 80;
+
+/// Network is one of the chains a node can run on. Each network has its
+/// own genesis block, message magic, port, seed nodes and address prefix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Network {
+    Main,
+    Test,
+    Regtest,
+}
+
+impl Network {
+    /// FromName parses a network name as given on the command line
+    pub fn from_name(name: &str) -> Result<Network> {
+        match name {
+            "main" => Ok(Network::Main),
+            "test" => Ok(Network::Test),
+            "regtest" => Ok(Network::Regtest),
+            _ => Err(format_err!("Unknown network {}", name)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Network::Main => "main",
+            Network::Test => "test",
+            Network::Regtest => "regtest",
+        }
+    }
+
+    /// GenesisCoinbaseData is the message embedded in the genesis coinbase
+    pub fn genesis_coinbase_data(&self) -> &'static str {
+        match self {
+            Network::Main => "The Times 03/Jan/2009 Chancellor on brink of second bailout for banks",
+            Network::Test => "Blockchain Rust test network genesis",
+            Network::Regtest => "Blockchain Rust regression test genesis",
+        }
+    }
+
+    /// Magic prefixes every message so nodes of different networks ignore each other
+    pub fn magic(&self) -> [u8; 4] {
+        match self {
+            Network::Main => [0xf9, 0xbe, 0xb4, 0xd9],
+            Network::Test => [0x0b, 0x11, 0x09, 0x07],
+            Network::Regtest => [0xfa, 0xbf, 0xb5, 0xda],
+        }
+    }
+
+    /// ChainId is committed to by every signature, so a transaction signed for
+    /// one network cannot be replayed on another
+    pub fn chain_id(&self) -> u32 {
+        match self {
+            Network::Main => 1,
+            Network::Test => 2,
+            Network::Regtest => 3,
+        }
+    }
+
+    pub fn default_port(&self) -> u16 {
+        match self {
+            Network::Main => 3000,
+            Network::Test => 13000,
+            Network::Regtest => 23000,
+        }
+    }
+
+    /// InitialBits is the difficulty of the genesis block
+    pub fn initial_bits(&self) -> u32 {
+        match self {
+            Network::Main | Network::Test => pow::INITIAL_BITS,
+            Network::Regtest => pow::REGTEST_BITS,
+        }
+    }
+
+    /// PowLimitBits is the easiest difficulty a block may have
+    pub fn pow_limit_bits(&self) -> u32 {
+        match self {
+            Network::Main | Network::Test => pow::POW_LIMIT_BITS,
+            Network::Regtest => pow::REGTEST_BITS,
+        }
+    }
+
+    /// PowNoRetargeting keeps the difficulty of the genesis block forever, so
+    /// regtest blocks can be generated instantly
+    pub fn pow_no_retargeting(&self) -> bool {
+        *self == Network::Regtest
+    }
+
+    /// HalvingInterval is the number of blocks between two halvings of the subsidy
+    pub fn halving_interval(&self) -> i32 {
+        match self {
+            Network::Main | Network::Test => 1000,
+            Network::Regtest => 150,
+        }
+    }
+
+    /// CoinbaseMaturity is the default number of blocks a coinbase output
+    /// must be buried under before it can be spent
+    pub fn coinbase_maturity(&self) -> i32 {
+        match self {
+            Network::Main | Network::Regtest => 100,
+            Network::Test => 10,
+        }
+    }
+
+    /// SeedNodes are the nodes contacted first when a node starts
+    pub fn seed_nodes(&self) -> Vec<String> {
+        vec![format!("localhost:{}", self.default_port())]
+    }
+
+    /// AddressNetwork selects the version byte of the addresses of the network
+    pub fn address_network(&self) -> AddressNetwork {
+        match self {
+            Network::Main => AddressNetwork::Main,
+            Network::Test => AddressNetwork::Test,
+            Network::Regtest => AddressNetwork::Regtest,
+        }
+    }
+}
+
+/// Config tells a node where its data lives and which network it is on
+#[derive(Debug, Clone)]
+pub struct Config {
+    datadir: PathBuf,
+    network: Network,
+    data_carrier_size: usize,
+    coinbase_maturity: i32,
+}
+
+impl Config {
+    pub fn new(datadir: &str, network: Network) -> Config {
+        Config {
+            datadir: PathBuf::from(datadir),
+            network,
+            data_carrier_size: DEFAULT_DATA_CARRIER_SIZE,
+            coinbase_maturity: network.coinbase_maturity(),
+        }
+    }
+
+    /// WithDataCarrierSize sets the number of bytes a data output of a loose
+    /// transaction may carry. Blocks are not held to it
+    pub fn with_data_carrier_size(mut self, size: usize) -> Config {
+        self.data_carrier_size = size;
+        self
+    }
+
+    /// WithCoinbaseMaturity overrides the maturity depth of coinbase outputs.
+    /// It is a consensus rule, so every node of the network must use the same
+    pub fn with_coinbase_maturity(mut self, depth: i32) -> Config {
+        self.coinbase_maturity = depth;
+        self
+    }
+
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    pub fn data_carrier_size(&self) -> usize {
+        self.data_carrier_size
+    }
+
+    pub fn coinbase_maturity(&self) -> i32 {
+        self.coinbase_maturity
+    }
+
+    /// NetworkDir is the directory holding the data of the network. The main
+    /// network uses the data directory itself
+    fn network_dir(&self) -> PathBuf {
+        match self.network {
+            Network::Main => self.datadir.clone(),
+            Network::Test => self.datadir.join("testnet"),
+            Network::Regtest => self.datadir.join("regtest"),
+        }
+    }
+
+    pub fn blocks_path(&self) -> PathBuf {
+        self.network_dir().join("blocks")
+    }
+
+    pub fn utxos_path(&self) -> PathBuf {
+        self.network_dir().join("utxos")
+    }
+
+    pub fn wallets_path(&self) -> PathBuf {
+        self.network_dir().join("wallets")
+    }
+}
@@ -1 +1,124 @@
+// failure's derive expands to impls inside an anonymous const
+#![allow(non_local_definitions)]
+
+use failure::Fail;
+
+use crate::amount::Amount;
+
 pub type Result<T> = std::result::Result<T, failure::Error>;
+
+/// BlockError is the reason a block was rejected by consensus validation
+#[derive(Debug, Fail)]
+pub enum BlockError {
+    #[fail(display = "block hash does not satisfy its proof of work")]
+    InvalidProofOfWork,
+    #[fail(display = "merkle root does not match the block transactions")]
+    BadMerkleRoot,
+    #[fail(display = "difficulty bits {:#010x} do not match expected {:#010x}", found, expected)]
+    BadDifficulty { expected: u32, found: u32 },
+    #[fail(display = "parent block {} is unknown", _0)]
+    UnknownParent(String),
+    #[fail(display = "block height {} does not match expected {}", found, expected)]
+    BadHeight { expected: i32, found: i32 },
+    #[fail(display = "block timestamp is not after the median time of the previous blocks")]
+    TimeTooOld,
+    #[fail(display = "block timestamp is too far in the future")]
+    TimeTooNew,
+    #[fail(display = "first transaction is not a coinbase")]
+    MissingCoinbase,
+    #[fail(display = "block contains more than one coinbase")]
+    MultipleCoinbase,
+    #[fail(display = "coinbase does not commit to the block height")]
+    BadCoinbaseHeight,
+    #[fail(display = "coinbase pays {} which exceeds the block reward {}", value, reward)]
+    BadCoinbaseValue { value: Amount, reward: Amount },
+    #[fail(display = "transaction {} has an id that does not match its contents", _0)]
+    BadTransactionId(String),
+    #[fail(display = "transaction {} has a value out of range", _0)]
+    ValueOutOfRange(String),
+    #[fail(display = "transaction {} has no inputs or outputs", _0)]
+    EmptyTransaction(String),
+    #[fail(display = "transaction {} spends missing or spent output {}:{}", txid, prev_txid, vout)]
+    MissingInput { txid: String, prev_txid: String, vout: i32 },
+    #[fail(display = "transaction {} spends more than its inputs", _0)]
+    OutputsExceedInputs(String),
+    #[fail(display = "transaction {} spends immature coinbase output {}:{}", txid, prev_txid, vout)]
+    ImmatureCoinbaseSpend { txid: String, prev_txid: String, vout: i32 },
+    #[fail(display = "output {}:{} is spent twice in the block", _0, _1)]
+    DuplicateSpend(String, i32),
+    #[fail(display = "transaction {} is locked until a later block", _0)]
+    NonFinalTransaction(String),
+    #[fail(display = "transaction {} spends an output before its relative lock time", _0)]
+    SequenceLocked(String),
+    #[fail(display = "transaction {} has an invalid signature", _0)]
+    BadSignature(String),
+}
+
+/// TxError is the reason a transaction was rejected before entering a block
+#[derive(Debug, Fail)]
+pub enum TxError {
+    #[fail(display = "transaction {} is a coinbase outside a block", _0)]
+    UnexpectedCoinbase(String),
+    #[fail(display = "transaction {} has no inputs or outputs", _0)]
+    EmptyTransaction(String),
+    #[fail(display = "transaction {} has an id that does not match its contents", _0)]
+    BadTransactionId(String),
+    #[fail(display = "transaction {} has a value out of range", _0)]
+    ValueOutOfRange(String),
+    #[fail(display = "transaction {} spends output {}:{} more than once", txid, prev_txid, vout)]
+    DuplicateInput { txid: String, prev_txid: String, vout: i32 },
+    #[fail(display = "transaction {} spends missing or spent output {}:{}", txid, prev_txid, vout)]
+    MissingInput { txid: String, prev_txid: String, vout: i32 },
+    #[fail(
+        display = "transaction {} spends output {}:{} already spent by mempool transaction {}",
+        txid, prev_txid, vout, conflict
+    )]
+    MempoolConflict { txid: String, prev_txid: String, vout: i32, conflict: String },
+    #[fail(display = "transaction {} spends immature coinbase output {}:{}", txid, prev_txid, vout)]
+    ImmatureCoinbaseSpend { txid: String, prev_txid: String, vout: i32 },
+    #[fail(display = "transaction {} spends more than its inputs", _0)]
+    OutputsExceedInputs(String),
+    #[fail(display = "transaction {} is locked until a later block", _0)]
+    NonFinalTransaction(String),
+    #[fail(display = "transaction {} spends an output before its relative lock time", _0)]
+    SequenceLocked(String),
+    #[fail(display = "transaction {} carries more than {} bytes of data in an output", _0, _1)]
+    DataCarrierTooLarge(String, usize),
+    #[fail(display = "transaction {} has an invalid signature", _0)]
+    BadSignature(String),
+}
+
+/// ScriptError is the reason a script failed to unlock an output
+#[derive(Debug, Fail, PartialEq, Eq)]
+pub enum ScriptError {
+    #[fail(display = "script has too many operations")]
+    OpCount,
+    #[fail(display = "stack holds too many items")]
+    StackSize,
+    #[fail(display = "operation needs more items than the stack holds")]
+    StackUnderflow,
+    #[fail(display = "unlocking script is not push only")]
+    SigPushOnly,
+    #[fail(display = "redeem script cannot be decoded")]
+    BadRedeemScript,
+    #[fail(display = "IF, ELSE and ENDIF are unbalanced")]
+    UnbalancedConditional,
+    #[fail(display = "output is provably unspendable")]
+    OpReturn,
+    #[fail(display = "VERIFY failed")]
+    Verify,
+    #[fail(display = "EQUALVERIFY failed")]
+    EqualVerify,
+    #[fail(display = "script evaluated to false")]
+    EvalFalse,
+    #[fail(display = "number is malformed or too large")]
+    InvalidNumber,
+    #[fail(display = "public key count is out of range")]
+    PubKeyCount,
+    #[fail(display = "signature count is out of range")]
+    SigCount,
+    #[fail(display = "lock time is negative")]
+    NegativeLockTime,
+    #[fail(display = "lock time is not satisfied")]
+    UnsatisfiedLockTime,
+}
@@ -7,6 +7,12 @@ mod cli;
 mod wallet;
 mod utxoset;
 mod server;
+mod pow;
+mod config;
+mod monetary;
+mod amount;
+mod script;
+mod psbt;
 
 use errors::Result;
 use cli::Cli;
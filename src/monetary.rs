@@ -0,0 +1,41 @@
+use crate::amount::{Amount, COIN};
+use crate::config::Network;
+
+/// Reward paid to the miner of a block before the first halving
+pub const INITIAL_SUBSIDY: Amount = Amount::from_base_units(100 * COIN);
+
+/// BlockSubsidy returns the newly issued coins a block at height may claim.
+/// The subsidy halves every halving interval of the network until it is zero
+pub fn block_subsidy(height: i32, network: Network) -> Amount {
+    let halvings = height / network.halving_interval();
+    if height < 0 || halvings >= 64 {
+        return Amount::ZERO;
+    }
+    Amount::from_base_units(INITIAL_SUBSIDY.base_units() >> halvings)
+}
+
+/// IssuedSupply returns the coins issued by the blocks up to height, both
+/// included, when every block claims its full subsidy
+pub fn issued_supply(height: i32, network: Network) -> Amount {
+    let interval = network.halving_interval() as u64;
+    let mut remaining = if height < 0 { 0 } else { height as u64 + 1 };
+    let mut subsidy = INITIAL_SUBSIDY.base_units();
+    let mut supply = 0;
+    while remaining > 0 && subsidy > 0 {
+        let blocks = remaining.min(interval);
+        supply += blocks * subsidy;
+        remaining -= blocks;
+        subsidy >>= 1;
+    }
+    Amount::from_base_units(supply)
+}
+
+/// MaxSupply returns the number of coins that will ever be issued
+pub fn max_supply(network: Network) -> Amount {
+    issued_supply(i32::MAX, network)
+}
+
+/// MoneyRange checks that an amount does not exceed the maximum supply
+pub fn money_range(value: Amount, network: Network) -> bool {
+    value <= max_supply(network)
+}
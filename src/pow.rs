@@ -0,0 +1,390 @@
+use std::cmp::Ordering;
+use std::ops::{Add, Div, Not, Shl, Shr, Sub};
+
+use failure::format_err;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::Result;
+
+/// Compact target used by the genesis block
+pub const INITIAL_BITS: u32 = 0x1f00ffff;
+/// Easiest target a block is allowed to have
+pub const POW_LIMIT_BITS: u32 = 0x2000ffff;
+/// Target of every regtest block, met by about half of all hashes
+pub const REGTEST_BITS: u32 = 0x207fffff;
+/// Number of blocks between two difficulty adjustments
+pub const RETARGET_INTERVAL: i32 = 10;
+/// Expected time between two blocks in milliseconds
+pub const TARGET_SPACING: u128 = 10_000;
+
+/// U256 is an unsigned 256-bit integer stored as big-endian 64-bit limbs
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct U256([u64; 4]);
+
+impl U256 {
+    pub fn zero() -> U256 {
+        U256([0; 4])
+    }
+
+    pub fn one() -> U256 {
+        U256::from_u64(1)
+    }
+
+    pub fn from_u64(v: u64) -> U256 {
+        U256([0, 0, 0, v])
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.iter().all(|l| *l == 0)
+    }
+
+    pub fn low_u64(&self) -> u64 {
+        self.0[3]
+    }
+
+    /// Bits returns the position of the highest set bit plus one
+    pub fn bits(&self) -> u32 {
+        for (i, l) in self.0.iter().enumerate() {
+            if *l != 0 {
+                return 64 * (4 - i as u32) - l.leading_zeros();
+            }
+        }
+        0
+    }
+
+    pub fn from_be_bytes(bytes: &[u8; 32]) -> U256 {
+        let mut limbs = [0u64; 4];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(&bytes[i * 8..i * 8 + 8]);
+            *limb = u64::from_be_bytes(b);
+        }
+        U256(limbs)
+    }
+
+    /// FromHash interprets a hex encoded block hash as a big-endian number
+    pub fn from_hash(hash: &str) -> Result<U256> {
+        if hash.len() != 64 || !hash.is_ascii() {
+            return Err(format_err!("Invalid block hash: {}", hash));
+        }
+        let mut bytes = [0u8; 32];
+        for (i, b) in bytes.iter_mut().enumerate() {
+            *b = u8::from_str_radix(&hash[i * 2..i * 2 + 2], 16)?;
+        }
+        Ok(U256::from_be_bytes(&bytes))
+    }
+
+    /// FromCompact decodes the "bits" representation of a target
+    pub fn from_compact(bits: u32) -> Result<U256> {
+        let size = bits >> 24;
+        let mut word = bits & 0x007fffff;
+        if bits & 0x00800000 != 0 && word != 0 {
+            return Err(format_err!("Negative compact target: {:#010x}", bits));
+        }
+        if size <= 3 {
+            word >>= 8 * (3 - size);
+            return Ok(U256::from_u64(word as u64));
+        }
+        let shift = 8 * (size - 3);
+        if word != 0 && shift + 32 - word.leading_zeros() > 256 {
+            return Err(format_err!("Compact target overflows: {:#010x}", bits));
+        }
+        Ok(U256::from_u64(word as u64) << shift)
+    }
+
+    /// ToCompact encodes the number in the "bits" representation
+    pub fn to_compact(self) -> u32 {
+        let mut size = self.bits().div_ceil(8);
+        let mut compact = if size <= 3 {
+            (self.low_u64() << (8 * (3 - size))) as u32
+        } else {
+            (self >> (8 * (size - 3))).low_u64() as u32
+        };
+        if compact & 0x00800000 != 0 {
+            compact >>= 8;
+            size += 1;
+        }
+        compact | (size << 24)
+    }
+
+    pub fn checked_mul_u64(&self, v: u64) -> Option<U256> {
+        let mut limbs = [0u64; 4];
+        let mut carry: u128 = 0;
+        for i in (0..4).rev() {
+            let n = self.0[i] as u128 * v as u128 + carry;
+            limbs[i] = n as u64;
+            carry = n >> 64;
+        }
+        if carry != 0 {
+            return None;
+        }
+        Some(U256(limbs))
+    }
+
+    pub fn div_u64(&self, v: u64) -> U256 {
+        let mut limbs = [0u64; 4];
+        let mut rem: u128 = 0;
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let n = (rem << 64) | self.0[i] as u128;
+            *limb = (n / v as u128) as u64;
+            rem = n % v as u128;
+        }
+        U256(limbs)
+    }
+
+    pub fn checked_add(&self, other: &U256) -> Option<U256> {
+        let mut limbs = [0u64; 4];
+        let mut carry = false;
+        for i in (0..4).rev() {
+            let (s1, c1) = self.0[i].overflowing_add(other.0[i]);
+            let (s2, c2) = s1.overflowing_add(carry as u64);
+            limbs[i] = s2;
+            carry = c1 || c2;
+        }
+        if carry {
+            return None;
+        }
+        Some(U256(limbs))
+    }
+}
+
+impl Ord for U256 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+impl PartialOrd for U256 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Add for U256 {
+    type Output = U256;
+
+    fn add(self, other: U256) -> U256 {
+        self.checked_add(&other).expect("U256 addition overflow")
+    }
+}
+
+impl Sub for U256 {
+    type Output = U256;
+
+    fn sub(self, other: U256) -> U256 {
+        let mut limbs = [0u64; 4];
+        let mut borrow = false;
+        for i in (0..4).rev() {
+            let (d1, b1) = self.0[i].overflowing_sub(other.0[i]);
+            let (d2, b2) = d1.overflowing_sub(borrow as u64);
+            limbs[i] = d2;
+            borrow = b1 || b2;
+        }
+        U256(limbs)
+    }
+}
+
+impl Not for U256 {
+    type Output = U256;
+
+    fn not(self) -> U256 {
+        U256([!self.0[0], !self.0[1], !self.0[2], !self.0[3]])
+    }
+}
+
+impl Div for U256 {
+    type Output = U256;
+
+    fn div(self, other: U256) -> U256 {
+        if other.is_zero() {
+            panic!("U256 division by zero");
+        }
+        let mut quotient = U256::zero();
+        let mut rem = self;
+        let other_bits = other.bits();
+        while rem >= other {
+            let mut shift = rem.bits() - other_bits;
+            if other << shift > rem {
+                shift -= 1;
+            }
+            rem = rem - (other << shift);
+            quotient = quotient + (U256::one() << shift);
+        }
+        quotient
+    }
+}
+
+impl Shl<u32> for U256 {
+    type Output = U256;
+
+    fn shl(self, shift: u32) -> U256 {
+        let mut limbs = [0u64; 4];
+        let word = (shift / 64) as usize;
+        let bit = shift % 64;
+        for (i, limb) in limbs.iter_mut().enumerate().take(4 - word.min(4)) {
+            *limb = self.0[i + word] << bit;
+            if bit > 0 && i + word < 3 {
+                *limb |= self.0[i + word + 1] >> (64 - bit);
+            }
+        }
+        U256(limbs)
+    }
+}
+
+impl Shr<u32> for U256 {
+    type Output = U256;
+
+    fn shr(self, shift: u32) -> U256 {
+        let mut limbs = [0u64; 4];
+        let word = (shift / 64) as usize;
+        let bit = shift % 64;
+        for i in (0..4).rev() {
+            if i < word {
+                break;
+            }
+            limbs[i] = self.0[i - word] >> bit;
+            if bit > 0 && i > word {
+                limbs[i] |= self.0[i - word - 1] << (64 - bit);
+            }
+        }
+        U256(limbs)
+    }
+}
+
+/// CheckProofOfWork checks that the hash satisfies the target encoded in bits
+/// and that the target is not easier than the limit of the network
+pub fn check_proof_of_work(hash: &str, bits: u32, limit_bits: u32) -> Result<bool> {
+    let target = U256::from_compact(bits)?;
+    if target.is_zero() || target > U256::from_compact(limit_bits)? {
+        return Err(format_err!("Target out of range: {:#010x}", bits));
+    }
+    meets_target(hash, bits)
+}
+
+/// MeetsTarget checks that the hash is not above the target encoded in bits
+pub fn meets_target(hash: &str, bits: u32) -> Result<bool> {
+    Ok(U256::from_hash(hash)? <= U256::from_compact(bits)?)
+}
+
+/// CalculateNextBits scales the previous target by the actual timespan of the
+/// last interval against the expected one, clamped to a factor of four
+pub fn calculate_next_bits(prev_bits: u32, actual_timespan: u128, limit_bits: u32) -> Result<u32> {
+    let expected = RETARGET_INTERVAL as u128 * TARGET_SPACING;
+    let timespan = actual_timespan.clamp(expected / 4, expected * 4);
+
+    let limit = U256::from_compact(limit_bits)?;
+    let target = U256::from_compact(prev_bits)?;
+    let new_target = match mul_div(target, timespan as u64, expected as u64) {
+        Some(t) if t <= limit && !t.is_zero() => t,
+        Some(t) if t.is_zero() => U256::one(),
+        _ => limit,
+    };
+    Ok(new_target.to_compact())
+}
+
+/// MulDiv returns value * mul / div rounded down, or None when it does not
+/// fit. Dividing first would truncate the target downwards, multiplying
+/// first overflows easy targets, so the remainder is scaled separately
+fn mul_div(value: U256, mul: u64, div: u64) -> Option<U256> {
+    let quotient = value.div_u64(div);
+    let remainder = (value - quotient.checked_mul_u64(div)?).low_u64();
+    let scaled_remainder = (remainder as u128 * mul as u128 / div as u128) as u64;
+    quotient.checked_mul_u64(mul)?.checked_add(&U256::from_u64(scaled_remainder))
+}
+
+/// BlockWork returns the expected number of hashes needed to meet the target
+pub fn block_work(bits: u32) -> Result<U256> {
+    let target = U256::from_compact(bits)?;
+    if target.is_zero() {
+        return Ok(U256::zero());
+    }
+    // 2**256 / (target + 1) does not fit, but equals ~target / (target + 1) + 1
+    Ok((!target / (target + U256::one())) + U256::one())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPECTED_TIMESPAN: u128 = RETARGET_INTERVAL as u128 * TARGET_SPACING;
+
+    #[test]
+    fn compact_round_trip() {
+        for bits in [0x1d00ffff, 0x03123456, 0x1f00ffff, POW_LIMIT_BITS, REGTEST_BITS, 0x05009234] {
+            assert_eq!(U256::from_compact(bits).unwrap().to_compact(), bits);
+        }
+        assert_eq!(U256::from_compact(0x03123456).unwrap(), U256::from_u64(0x123456));
+        assert_eq!(U256::from_compact(0x01123456).unwrap(), U256::from_u64(0x12));
+        assert_eq!(U256::from_compact(0x1d00ffff).unwrap(), U256::from_u64(0xffff) << 208);
+        // a sign bit would be set, so the mantissa moves to the next byte
+        assert_eq!(U256::from_u64(0x80).to_compact(), 0x02008000);
+        assert_eq!(U256::zero().to_compact(), 0);
+    }
+
+    #[test]
+    fn compact_out_of_range() {
+        assert!(U256::from_compact(0x04923456).is_err());
+        assert!(U256::from_compact(0x01fedcba).is_err());
+        assert!(U256::from_compact(0xff123456).is_err());
+        assert!(U256::from_compact(0x21010000).is_err());
+        // a negative zero is zero
+        assert_eq!(U256::from_compact(0x04800000).unwrap(), U256::zero());
+        assert!(U256::from_compact(0x207fffff).is_ok());
+    }
+
+    #[test]
+    fn arithmetic() {
+        let a = U256::from_u64(u64::MAX) << 100;
+        assert_eq!(a >> 100, U256::from_u64(u64::MAX));
+        assert_eq!((a >> 36).bits(), 128);
+        assert_eq!(U256::one() << 255 >> 255, U256::one());
+        assert_eq!(U256::one() << 256, U256::zero());
+        assert_eq!(a / (U256::one() << 100), U256::from_u64(u64::MAX));
+        assert_eq!(a / a, U256::one());
+        assert_eq!(U256::from_u64(7) / U256::from_u64(2), U256::from_u64(3));
+        assert_eq!(a.div_u64(3), a / U256::from_u64(3));
+        assert_eq!(a.checked_mul_u64(4), Some(a << 2));
+        assert_eq!((U256::one() << 255).checked_mul_u64(2), None);
+        assert_eq!((!U256::zero()).checked_add(&U256::one()), None);
+        assert_eq!(a - a, U256::zero());
+        assert_eq!(mul_div(U256::from_u64(10), 3, 4), Some(U256::from_u64(7)));
+        assert_eq!(mul_div(!U256::zero(), 3, 4), Some((!U256::zero()).div_u64(4).checked_mul_u64(3).unwrap() + U256::from_u64(2)));
+        assert_eq!(mul_div(!U256::zero(), 4, 3), None);
+    }
+
+    #[test]
+    fn retarget_on_schedule_keeps_the_target() {
+        for bits in [0x1f00ffff, 0x1d00ffff, 0x1e0fffff] {
+            assert_eq!(calculate_next_bits(bits, EXPECTED_TIMESPAN, POW_LIMIT_BITS).unwrap(), bits);
+        }
+    }
+
+    #[test]
+    fn retarget_is_clamped() {
+        let bits = 0x1d00ffff;
+        let target = U256::from_compact(bits).unwrap();
+        let slowest = target.checked_mul_u64(4).unwrap().to_compact();
+        let fastest = target.div_u64(4).to_compact();
+        assert_eq!(calculate_next_bits(bits, EXPECTED_TIMESPAN * 4, POW_LIMIT_BITS).unwrap(), slowest);
+        assert_eq!(calculate_next_bits(bits, EXPECTED_TIMESPAN * 100, POW_LIMIT_BITS).unwrap(), slowest);
+        assert_eq!(calculate_next_bits(bits, EXPECTED_TIMESPAN / 4, POW_LIMIT_BITS).unwrap(), fastest);
+        assert_eq!(calculate_next_bits(bits, 0, POW_LIMIT_BITS).unwrap(), fastest);
+        assert_eq!(calculate_next_bits(bits, EXPECTED_TIMESPAN * 2, POW_LIMIT_BITS).unwrap(), 0x1d01fffe);
+    }
+
+    #[test]
+    fn retarget_never_exceeds_the_limit() {
+        assert_eq!(calculate_next_bits(POW_LIMIT_BITS, EXPECTED_TIMESPAN * 4, POW_LIMIT_BITS).unwrap(), POW_LIMIT_BITS);
+        // the multiplication overflows 256 bits
+        assert_eq!(calculate_next_bits(REGTEST_BITS, EXPECTED_TIMESPAN * 4, REGTEST_BITS).unwrap(), REGTEST_BITS);
+    }
+
+    #[test]
+    fn work() {
+        assert_eq!(block_work(REGTEST_BITS).unwrap(), U256::from_u64(2));
+        assert_eq!(block_work(POW_LIMIT_BITS).unwrap(), U256::from_u64(256));
+        assert_eq!(block_work(0x1d00ffff).unwrap(), U256::from_u64(0x1_0001_0001));
+        assert_eq!(block_work(0).unwrap(), U256::zero());
+        assert!(block_work(0x1d00ffff).unwrap() > block_work(0x1f00ffff).unwrap());
+    }
+}
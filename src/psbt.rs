@@ -0,0 +1,220 @@
+use std::collections::BTreeMap;
+
+use failure::format_err;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Network;
+use crate::errors::Result;
+use crate::script::{self, Script};
+use crate::transaction::Transaction;
+use crate::tx::TXOutput;
+use crate::utxoset::UTXOSet;
+use crate::wallet::{hash_pub_key, Wallet};
+
+/// PsbtInput holds what an offline signer needs to sign an input, and the
+/// signatures collected for it by public key
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PsbtInput {
+    pub prev_output: TXOutput,
+    pub redeem_script: Option<Script>,
+    pub signatures: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+/// PartiallySignedTransaction carries an unsigned transaction between the
+/// online node creating it, the signers and the node broadcasting it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PartiallySignedTransaction {
+    pub tx: Transaction,
+    pub inputs: Vec<PsbtInput>,
+}
+
+impl PartiallySignedTransaction {
+    /// New looks up the outputs spent by the unsigned transaction. The redeem
+    /// script is attached to the inputs spending its P2SH outputs
+    pub fn new(tx: Transaction, redeem_script: Option<&Script>, bc: &UTXOSet) -> Result<PartiallySignedTransaction> {
+        let script_hash = match redeem_script {
+            Some(redeem_script) => Some(script::hash160(&redeem_script.to_bytes()?)),
+            None => None,
+        };
+        let mut inputs = Vec::new();
+        for vin in &tx.vin {
+            let prev_tx = bc.blockchain.find_transaction(&vin.txid)?;
+            let prev_output = prev_tx
+                .vout
+                .get(vin.vout as usize)
+                .cloned()
+                .ok_or_else(|| format_err!("Output {}:{} does not exist", vin.txid, vin.vout))?;
+            let redeem_script = match prev_output.script_pubkey.p2sh_hash() {
+                Some(hash) if Some(hash) == script_hash.as_deref() => redeem_script.cloned(),
+                _ => None,
+            };
+            inputs.push(PsbtInput {
+                prev_output,
+                redeem_script,
+                signatures: BTreeMap::new(),
+            });
+        }
+        Ok(PartiallySignedTransaction { tx, inputs })
+    }
+
+    /// FromHex decodes a PSBT and recomputes the id of its transaction, which
+    /// is not trusted from the encoding
+    pub fn from_hex(data: &str) -> Result<PartiallySignedTransaction> {
+        let mut psbt: PartiallySignedTransaction = bincode::deserialize(&hex::decode(data)?)?;
+        psbt.tx.id = psbt.tx.hash()?;
+        if psbt.inputs.len() != psbt.tx.vin.len() {
+            return Err(format_err!("PSBT has {} inputs for {} transaction inputs", psbt.inputs.len(), psbt.tx.vin.len()));
+        }
+        Ok(psbt)
+    }
+
+    pub fn to_hex(&self) -> Result<String> {
+        Ok(hex::encode(bincode::serialize(self)?))
+    }
+
+    /// Sign adds the signature of the wallet to the inputs it can sign, P2PKH
+    /// ones of its key and multisig ones it is a signer of. It needs neither
+    /// the chain nor the UTXO set, and returns the number of signatures added
+    pub fn sign(&mut self, wallet: &Wallet, sighash_type: u8, network: Network) -> Result<usize> {
+        let mut pub_key_hash = wallet.public_key.clone();
+        hash_pub_key(&mut pub_key_hash);
+
+        let mut signed = 0;
+        for (in_id, input) in self.inputs.iter_mut().enumerate() {
+            let script_pubkey = &input.prev_output.script_pubkey;
+            let script_code = if script_pubkey.p2pkh_hash() == Some(&pub_key_hash[..]) {
+                script_pubkey
+            } else {
+                match &input.redeem_script {
+                    Some(redeem_script) if is_signer(redeem_script, &wallet.public_key) => redeem_script,
+                    _ => continue,
+                }
+            };
+            let signature = self.tx.sign_input(in_id, script_code, sighash_type, network, wallet)?;
+            input.signatures.insert(wallet.public_key.clone(), signature);
+            signed += 1;
+        }
+        Ok(signed)
+    }
+
+    /// Combine merges the signatures collected by another copy of the same
+    /// transaction spending the same outputs
+    pub fn combine(&mut self, other: PartiallySignedTransaction) -> Result<()> {
+        let (id, other_id) = (self.tx.hash()?, other.tx.hash()?);
+        let same_inputs = self.inputs.len() == other.inputs.len()
+            && self.inputs.iter().zip(&other.inputs).all(|(input, other_input)| {
+                input.prev_output == other_input.prev_output && input.redeem_script == other_input.redeem_script
+            });
+        if id != other_id || !same_inputs {
+            return Err(format_err!("Cannot combine PSBTs of transactions {} and {}", id, other_id));
+        }
+        for (input, other_input) in self.inputs.iter_mut().zip(other.inputs) {
+            input.signatures.extend(other_input.signatures);
+        }
+        Ok(())
+    }
+
+    /// Finalize builds the unlocking script of every input from the collected
+    /// signatures, failing when some input does not have enough of them
+    pub fn finalize(&self) -> Result<Transaction> {
+        let mut tx = self.tx.clone();
+        for (in_id, input) in self.inputs.iter().enumerate() {
+            tx.vin[in_id].script_sig = input.finalize().ok_or_else(|| format_err!("Input {} is not fully signed", in_id))??;
+        }
+        Ok(tx)
+    }
+}
+
+impl PsbtInput {
+    /// Finalize returns the unlocking script of the input, or None when its
+    /// signatures are missing
+    fn finalize(&self) -> Option<Result<Script>> {
+        let script_pubkey = &self.prev_output.script_pubkey;
+        if let Some(hash) = script_pubkey.p2pkh_hash() {
+            let (pub_key, signature) = self.signatures.iter().find(|(pub_key, _)| {
+                let mut pub_key_hash = pub_key.to_vec();
+                hash_pub_key(&mut pub_key_hash);
+                pub_key_hash == hash
+            })?;
+            return Some(Ok(Script::new_p2pkh_sig(signature, pub_key)));
+        }
+
+        let redeem_script = self.redeem_script.as_ref()?;
+        let (m, pub_keys) = redeem_script.multisig_keys()?;
+        let signatures: Vec<Vec<u8>> = pub_keys
+            .iter()
+            .filter_map(|pub_key| self.signatures.get(*pub_key))
+            .take(m)
+            .cloned()
+            .collect();
+        if signatures.len() < m {
+            return None;
+        }
+        Some(Script::new_multisig_sig(&signatures, redeem_script))
+    }
+}
+
+fn is_signer(redeem_script: &Script, pub_key: &[u8]) -> bool {
+    match redeem_script.multisig_keys() {
+        Some((_, pub_keys)) => pub_keys.contains(&pub_key),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::amount::Amount;
+    use crate::transaction::SEQUENCE_FINAL;
+    use crate::tx::TXInput;
+
+    fn psbt(value: u64) -> PartiallySignedTransaction {
+        let output = TXOutput {
+            value: Amount::from_base_units(value),
+            script_pubkey: Script::new_p2pkh(&[1; 20]),
+        };
+        let mut tx = Transaction {
+            id: String::new(),
+            vin: vec![TXInput {
+                txid: hex::encode([2; 32]),
+                vout: 0,
+                script_sig: Script::default(),
+                sequence: SEQUENCE_FINAL,
+            }],
+            vout: vec![output.clone()],
+            lock_time: 0,
+        };
+        tx.id = tx.hash().unwrap();
+        let input = PsbtInput {
+            prev_output: output,
+            redeem_script: None,
+            signatures: BTreeMap::new(),
+        };
+        PartiallySignedTransaction { tx, inputs: vec![input] }
+    }
+
+    #[test]
+    fn from_hex_recomputes_the_id() {
+        let mut tampered = psbt(1);
+        tampered.tx.id = psbt(2).tx.id;
+        let decoded = PartiallySignedTransaction::from_hex(&tampered.to_hex().unwrap()).unwrap();
+        assert_eq!(decoded.tx.id, psbt(1).tx.id);
+    }
+
+    #[test]
+    fn combine_requires_the_same_transaction() {
+        let mut other = psbt(2);
+        other.tx.id = psbt(1).tx.id;
+        assert!(psbt(1).combine(other).is_err());
+
+        let mut other = psbt(1);
+        other.inputs[0].prev_output.value = Amount::ZERO;
+        assert!(psbt(1).combine(other).is_err());
+
+        let mut other = psbt(1);
+        other.inputs[0].signatures.insert(vec![3; 32], vec![4; 65]);
+        let mut combined = psbt(1);
+        combined.combine(other).unwrap();
+        assert_eq!(combined.inputs[0].signatures.len(), 1);
+    }
+}
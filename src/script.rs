@@ -0,0 +1,800 @@
+use std::fmt;
+use std::str::FromStr;
+
+use bitcoincash_addr::{Address, HashType};
+use crypto::{digest::Digest, ripemd160::Ripemd160, sha2::Sha256};
+use failure::format_err;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::{Result, ScriptError};
+
+/// Maximum number of operations other than pushes in a script
+const MAX_OPS_PER_SCRIPT: usize = 201;
+/// Maximum number of items on the stack
+const MAX_STACK_SIZE: usize = 1000;
+/// Maximum number of public keys a CHECKMULTISIG may check
+pub const MAX_PUBKEYS_PER_MULTISIG: usize = 20;
+/// Maximum size of a number operand
+const MAX_NUM_SIZE: usize = 4;
+/// Maximum size of the operand of CHECKLOCKTIMEVERIFY, large enough for timestamps
+const LOCK_TIME_NUM_SIZE: usize = 5;
+
+/// Op is a single operation of a script
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    Push(Vec<u8>),
+    Dup,
+    Drop,
+    Hash160,
+    Sha256,
+    Equal,
+    EqualVerify,
+    Verify,
+    CheckSig,
+    CheckMultiSig,
+    If,
+    Else,
+    EndIf,
+    CheckLockTimeVerify,
+    Return,
+}
+
+/// Htlc holds the terms of a hash time-locked contract: the recipient may
+/// spend it by revealing the preimage of the hash, the refund key once the
+/// lock time has passed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Htlc {
+    pub hash: Vec<u8>,
+    pub recipient: Vec<u8>,
+    pub refund: Vec<u8>,
+    pub lock_time: u32,
+}
+
+/// Script is a small stack program. Outputs are locked by a script_pubkey and
+/// inputs unlock them with a script_sig pushing the data it needs
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct Script(pub Vec<Op>);
+
+impl Script {
+    pub fn new(ops: Vec<Op>) -> Script {
+        Script(ops)
+    }
+
+    /// NewP2PKH locks an output to the owner of a public key hash
+    pub fn new_p2pkh(pub_key_hash: &[u8]) -> Script {
+        Script(vec![
+            Op::Dup,
+            Op::Hash160,
+            Op::Push(pub_key_hash.to_vec()),
+            Op::EqualVerify,
+            Op::CheckSig,
+        ])
+    }
+
+    /// NewP2PKHSig unlocks a P2PKH output with a signature and the public key
+    pub fn new_p2pkh_sig(signature: &[u8], pub_key: &[u8]) -> Script {
+        Script(vec![Op::Push(signature.to_vec()), Op::Push(pub_key.to_vec())])
+    }
+
+    /// NewP2SH locks an output to a redeem script given by its spender
+    pub fn new_p2sh(script_hash: &[u8]) -> Script {
+        Script(vec![Op::Hash160, Op::Push(script_hash.to_vec()), Op::Equal])
+    }
+
+    /// NewMultisig returns the redeem script requiring m signatures of the
+    /// public keys. Keys are sorted, so the script does not depend on their order
+    pub fn new_multisig(m: usize, pub_keys: &[Vec<u8>]) -> Script {
+        let mut pub_keys = pub_keys.to_vec();
+        pub_keys.sort();
+        let mut ops = vec![Op::Push(encode_num(m as i64))];
+        ops.extend(pub_keys.into_iter().map(Op::Push));
+        ops.push(Op::Push(encode_num(ops.len() as i64 - 1)));
+        ops.push(Op::CheckMultiSig);
+        Script(ops)
+    }
+
+    /// NewMultisigSig unlocks a P2SH multisig output with signatures in the
+    /// order of the keys of the redeem script. Partially signed inputs hold
+    /// one slot per key, empty for the keys that did not sign yet
+    pub fn new_multisig_sig(signatures: &[Vec<u8>], redeem_script: &Script) -> Result<Script> {
+        let mut ops: Vec<Op> = signatures.iter().cloned().map(Op::Push).collect();
+        ops.push(Op::Push(redeem_script.to_bytes()?));
+        Ok(Script(ops))
+    }
+
+    /// NewHTLC returns the redeem script of a hash time-locked contract
+    pub fn new_htlc(htlc: &Htlc) -> Script {
+        Script(vec![
+            Op::If,
+            Op::Sha256,
+            Op::Push(htlc.hash.clone()),
+            Op::EqualVerify,
+            Op::Dup,
+            Op::Hash160,
+            Op::Push(htlc.recipient.clone()),
+            Op::Else,
+            Op::Push(encode_num(htlc.lock_time as i64)),
+            Op::CheckLockTimeVerify,
+            Op::Drop,
+            Op::Dup,
+            Op::Hash160,
+            Op::Push(htlc.refund.clone()),
+            Op::EndIf,
+            Op::EqualVerify,
+            Op::CheckSig,
+        ])
+    }
+
+    /// NewHTLCSig unlocks a P2SH HTLC output, through the hash branch when
+    /// the preimage is given and through the refund branch otherwise
+    pub fn new_htlc_sig(
+        signature: &[u8],
+        pub_key: &[u8],
+        preimage: Option<&[u8]>,
+        redeem_script: &Script,
+    ) -> Result<Script> {
+        let mut ops = vec![Op::Push(signature.to_vec()), Op::Push(pub_key.to_vec())];
+        match preimage {
+            Some(preimage) => ops.extend([Op::Push(preimage.to_vec()), Op::Push(vec![1])]),
+            None => ops.push(Op::Push(Vec::new())),
+        }
+        ops.push(Op::Push(redeem_script.to_bytes()?));
+        Ok(Script(ops))
+    }
+
+    /// NewData returns a provably unspendable script carrying data
+    pub fn new_data(data: &[u8]) -> Script {
+        Script(vec![Op::Return, Op::Push(data.to_vec())])
+    }
+
+    /// FromAddress returns the script locking outputs paid to an address
+    pub fn from_address(address: &Address) -> Script {
+        match address.hash_type {
+            HashType::Key => Script::new_p2pkh(&address.body),
+            HashType::Script => Script::new_p2sh(&address.body),
+        }
+    }
+
+    /// IsUnspendable checks whether the script fails whatever unlocks it, so
+    /// its output never enters the UTXO set
+    pub fn is_unspendable(&self) -> bool {
+        self.0.first() == Some(&Op::Return)
+    }
+
+    /// Data returns the data carried by a script built by NewData
+    pub fn data(&self) -> Option<&[u8]> {
+        match self.0.as_slice() {
+            [Op::Return, Op::Push(data)] => Some(data),
+            _ => None,
+        }
+    }
+
+    /// P2PKHHash returns the public key hash locking a P2PKH script
+    pub fn p2pkh_hash(&self) -> Option<&[u8]> {
+        match self.0.as_slice() {
+            [Op::Dup, Op::Hash160, Op::Push(hash), Op::EqualVerify, Op::CheckSig] => Some(hash),
+            _ => None,
+        }
+    }
+
+    /// P2SHHash returns the redeem script hash locking a P2SH script
+    pub fn p2sh_hash(&self) -> Option<&[u8]> {
+        match self.0.as_slice() {
+            [Op::Hash160, Op::Push(hash), Op::Equal] => Some(hash),
+            _ => None,
+        }
+    }
+
+    /// MultisigKeys returns the number of signatures and the public keys of a
+    /// multisig redeem script
+    pub fn multisig_keys(&self) -> Option<(usize, Vec<&[u8]>)> {
+        let (first, rest) = self.0.split_first()?;
+        let (last, rest) = rest.split_last()?;
+        let (count, keys) = rest.split_last()?;
+        let m = match first {
+            Op::Push(m) => decode_num(m, MAX_NUM_SIZE).ok()?,
+            _ => return None,
+        };
+        let n = match count {
+            Op::Push(n) => decode_num(n, MAX_NUM_SIZE).ok()?,
+            _ => return None,
+        };
+        let keys: Vec<&[u8]> = keys
+            .iter()
+            .map(|op| match op {
+                Op::Push(key) => Some(key.as_slice()),
+                _ => None,
+            })
+            .collect::<Option<_>>()?;
+        if *last != Op::CheckMultiSig || n as usize != keys.len() || m < 1 || m > n {
+            return None;
+        }
+        Some((m as usize, keys))
+    }
+
+    /// HTLC returns the terms of an HTLC redeem script
+    pub fn htlc(&self) -> Option<Htlc> {
+        match self.0.as_slice() {
+            [
+                Op::If,
+                Op::Sha256,
+                Op::Push(hash),
+                Op::EqualVerify,
+                Op::Dup,
+                Op::Hash160,
+                Op::Push(recipient),
+                Op::Else,
+                Op::Push(lock_time),
+                Op::CheckLockTimeVerify,
+                Op::Drop,
+                Op::Dup,
+                Op::Hash160,
+                Op::Push(refund),
+                Op::EndIf,
+                Op::EqualVerify,
+                Op::CheckSig,
+            ] => Some(Htlc {
+                hash: hash.clone(),
+                recipient: recipient.clone(),
+                refund: refund.clone(),
+                lock_time: u32::try_from(decode_num(lock_time, LOCK_TIME_NUM_SIZE).ok()?).ok()?,
+            }),
+            _ => None,
+        }
+    }
+
+    /// RedeemScript returns the redeem script pushed last by a P2SH script_sig
+    /// and the data pushed before it
+    pub fn redeem_script(&self) -> Option<(Script, Vec<Vec<u8>>)> {
+        let (last, rest) = self.0.split_last()?;
+        let redeem_script = match last {
+            Op::Push(data) => Script::from_bytes(data).ok()?,
+            _ => return None,
+        };
+        let pushes = rest
+            .iter()
+            .map(|op| match op {
+                Op::Push(data) => Some(data.clone()),
+                _ => None,
+            })
+            .collect::<Option<_>>()?;
+        Some((redeem_script, pushes))
+    }
+
+    /// ToBytes serializes the script, as pushed by a P2SH script_sig
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Script> {
+        Ok(bincode::deserialize(data)?)
+    }
+
+    pub fn is_push_only(&self) -> bool {
+        self.0.iter().all(|op| matches!(op, Op::Push(_)))
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Op::Push(data) if data.is_empty() => "0",
+            Op::Push(data) => return write!(f, "{}", hex::encode(data)),
+            Op::Dup => "OP_DUP",
+            Op::Drop => "OP_DROP",
+            Op::Hash160 => "OP_HASH160",
+            Op::Sha256 => "OP_SHA256",
+            Op::Equal => "OP_EQUAL",
+            Op::EqualVerify => "OP_EQUALVERIFY",
+            Op::Verify => "OP_VERIFY",
+            Op::CheckSig => "OP_CHECKSIG",
+            Op::CheckMultiSig => "OP_CHECKMULTISIG",
+            Op::If => "OP_IF",
+            Op::Else => "OP_ELSE",
+            Op::EndIf => "OP_ENDIF",
+            Op::CheckLockTimeVerify => "OP_CHECKLOCKTIMEVERIFY",
+            Op::Return => "OP_RETURN",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Op {
+    type Err = failure::Error;
+
+    /// FromStr parses an operation name, or the hex of the data to push
+    fn from_str(s: &str) -> Result<Op> {
+        let op = match s {
+            "0" => Op::Push(Vec::new()),
+            "OP_DUP" => Op::Dup,
+            "OP_DROP" => Op::Drop,
+            "OP_HASH160" => Op::Hash160,
+            "OP_SHA256" => Op::Sha256,
+            "OP_EQUAL" => Op::Equal,
+            "OP_EQUALVERIFY" => Op::EqualVerify,
+            "OP_VERIFY" => Op::Verify,
+            "OP_CHECKSIG" => Op::CheckSig,
+            "OP_CHECKMULTISIG" => Op::CheckMultiSig,
+            "OP_IF" => Op::If,
+            "OP_ELSE" => Op::Else,
+            "OP_ENDIF" => Op::EndIf,
+            "OP_CHECKLOCKTIMEVERIFY" => Op::CheckLockTimeVerify,
+            "OP_RETURN" => Op::Return,
+            _ => Op::Push(hex::decode(s).map_err(|_| format_err!("Invalid script operation {}", s))?),
+        };
+        Ok(op)
+    }
+}
+
+impl fmt::Display for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ops: Vec<String> = self.0.iter().map(|op| op.to_string()).collect();
+        write!(f, "{}", ops.join(" "))
+    }
+}
+
+impl fmt::Debug for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Script").field(&self.to_string()).finish()
+    }
+}
+
+impl FromStr for Script {
+    type Err = failure::Error;
+
+    /// FromStr parses the space separated operations printed by Display
+    fn from_str(s: &str) -> Result<Script> {
+        Ok(Script(s.split_whitespace().map(Op::from_str).collect::<Result<_>>()?))
+    }
+}
+
+/// SignatureChecker gives a script access to the transaction spending the output
+pub trait SignatureChecker {
+    /// CheckSig verifies a signature of the spending transaction. The script
+    /// code is the script holding the CHECKSIG, which the signature commits to
+    fn check_sig(&self, signature: &[u8], pub_key: &[u8], script_code: &Script) -> bool;
+
+    /// CheckLockTime checks that the spending transaction cannot be mined
+    /// before lock_time
+    fn check_lock_time(&self, lock_time: i64) -> bool;
+}
+
+/// VerifyScript checks that script_sig unlocks script_pubkey. The unlocking
+/// script may only push data, then the locking script runs on the resulting
+/// stack and must leave true on top. A P2SH script_pubkey only checks the
+/// hash of the redeem script pushed last, which then runs on the data pushed
+/// before it
+pub fn verify_script(
+    script_sig: &Script,
+    script_pubkey: &Script,
+    checker: &dyn SignatureChecker,
+) -> std::result::Result<(), ScriptError> {
+    if !script_sig.is_push_only() {
+        return Err(ScriptError::SigPushOnly);
+    }
+    let mut stack = Vec::new();
+    eval(script_sig, &mut stack, checker)?;
+    let mut p2sh_stack = stack.clone();
+    eval(script_pubkey, &mut stack, checker)?;
+    check_top(&stack)?;
+
+    if script_pubkey.p2sh_hash().is_some() {
+        let redeem_script = Script::from_bytes(&pop(&mut p2sh_stack)?).map_err(|_| ScriptError::BadRedeemScript)?;
+        eval(&redeem_script, &mut p2sh_stack, checker)?;
+        check_top(&p2sh_stack)?;
+    }
+    Ok(())
+}
+
+fn check_top(stack: &[Vec<u8>]) -> std::result::Result<(), ScriptError> {
+    match stack.last() {
+        Some(top) if cast_to_bool(top) => Ok(()),
+        _ => Err(ScriptError::EvalFalse),
+    }
+}
+
+/// Eval runs a script on the stack
+pub fn eval(
+    script: &Script,
+    stack: &mut Vec<Vec<u8>>,
+    checker: &dyn SignatureChecker,
+) -> std::result::Result<(), ScriptError> {
+    // one entry per open IF, telling whether its current branch runs
+    let mut branches: Vec<bool> = Vec::new();
+    let mut op_count = 0;
+
+    for op in &script.0 {
+        if !matches!(op, Op::Push(_)) {
+            op_count += 1;
+            if op_count > MAX_OPS_PER_SCRIPT {
+                return Err(ScriptError::OpCount);
+            }
+        }
+        let executing = branches.iter().all(|branch| *branch);
+
+        match op {
+            Op::If => {
+                let branch = executing && cast_to_bool(&pop(stack)?);
+                branches.push(branch);
+            }
+            Op::Else => {
+                let branch = branches.last_mut().ok_or(ScriptError::UnbalancedConditional)?;
+                *branch = !*branch;
+            }
+            Op::EndIf => {
+                branches.pop().ok_or(ScriptError::UnbalancedConditional)?;
+            }
+            _ if !executing => {}
+            Op::Push(data) => stack.push(data.clone()),
+            Op::Dup => {
+                let top = stack.last().ok_or(ScriptError::StackUnderflow)?.clone();
+                stack.push(top);
+            }
+            Op::Drop => {
+                pop(stack)?;
+            }
+            Op::Hash160 => {
+                let data = pop(stack)?;
+                stack.push(hash160(&data));
+            }
+            Op::Sha256 => {
+                let data = pop(stack)?;
+                stack.push(sha256(&data));
+            }
+            Op::Equal => {
+                let equal = pop(stack)? == pop(stack)?;
+                stack.push(bool_to_bytes(equal));
+            }
+            Op::EqualVerify => {
+                if pop(stack)? != pop(stack)? {
+                    return Err(ScriptError::EqualVerify);
+                }
+            }
+            Op::Verify => {
+                if !cast_to_bool(&pop(stack)?) {
+                    return Err(ScriptError::Verify);
+                }
+            }
+            Op::CheckSig => {
+                let pub_key = pop(stack)?;
+                let signature = pop(stack)?;
+                let valid = checker.check_sig(&signature, &pub_key, script);
+                stack.push(bool_to_bytes(valid));
+            }
+            Op::CheckMultiSig => {
+                let n = decode_num(&pop(stack)?, MAX_NUM_SIZE)?;
+                if n < 0 || n as usize > MAX_PUBKEYS_PER_MULTISIG {
+                    return Err(ScriptError::PubKeyCount);
+                }
+                op_count += n as usize;
+                if op_count > MAX_OPS_PER_SCRIPT {
+                    return Err(ScriptError::OpCount);
+                }
+                let pub_keys = pop_n(stack, n as usize)?;
+                let m = decode_num(&pop(stack)?, MAX_NUM_SIZE)?;
+                if m < 0 || m > n {
+                    return Err(ScriptError::SigCount);
+                }
+                let signatures = pop_n(stack, m as usize)?;
+                // signatures are in the order of the keys, so each key signs once
+                let mut keys = pub_keys.iter();
+                let valid = signatures
+                    .iter()
+                    .all(|signature| keys.any(|pub_key| checker.check_sig(signature, pub_key, script)));
+                stack.push(bool_to_bytes(valid));
+            }
+            Op::CheckLockTimeVerify => {
+                let top = stack.last().ok_or(ScriptError::StackUnderflow)?;
+                let lock_time = decode_num(top, LOCK_TIME_NUM_SIZE)?;
+                if lock_time < 0 {
+                    return Err(ScriptError::NegativeLockTime);
+                }
+                if !checker.check_lock_time(lock_time) {
+                    return Err(ScriptError::UnsatisfiedLockTime);
+                }
+            }
+            Op::Return => return Err(ScriptError::OpReturn),
+        }
+
+        if stack.len() > MAX_STACK_SIZE {
+            return Err(ScriptError::StackSize);
+        }
+    }
+
+    if !branches.is_empty() {
+        return Err(ScriptError::UnbalancedConditional);
+    }
+    Ok(())
+}
+
+pub fn sha256(data: &[u8]) -> Vec<u8> {
+    let mut hash = vec![0u8; 32];
+    let mut hasher = Sha256::new();
+    hasher.input(data);
+    hasher.result(&mut hash);
+    hash
+}
+
+/// Hash160 is RIPEMD160(SHA256(data)), the hash of public keys in scripts
+pub fn hash160(data: &[u8]) -> Vec<u8> {
+    let mut sha = [0u8; 32];
+    let mut hasher1 = Sha256::new();
+    hasher1.input(data);
+    hasher1.result(&mut sha);
+    let mut hash = vec![0u8; 20];
+    let mut hasher2 = Ripemd160::new();
+    hasher2.input(&sha);
+    hasher2.result(&mut hash);
+    hash
+}
+
+fn pop(stack: &mut Vec<Vec<u8>>) -> std::result::Result<Vec<u8>, ScriptError> {
+    stack.pop().ok_or(ScriptError::StackUnderflow)
+}
+
+/// PopN removes the n top items of the stack, keeping their order
+fn pop_n(stack: &mut Vec<Vec<u8>>, n: usize) -> std::result::Result<Vec<Vec<u8>>, ScriptError> {
+    if stack.len() < n {
+        return Err(ScriptError::StackUnderflow);
+    }
+    Ok(stack.split_off(stack.len() - n))
+}
+
+/// CastToBool is false for empty data, zeros and negative zero
+fn cast_to_bool(data: &[u8]) -> bool {
+    match data.split_last() {
+        Some((last, rest)) => rest.iter().any(|b| *b != 0) || (*last != 0 && *last != 0x80),
+        None => false,
+    }
+}
+
+fn bool_to_bytes(value: bool) -> Vec<u8> {
+    if value {
+        vec![1]
+    } else {
+        Vec::new()
+    }
+}
+
+/// EncodeNum writes a number as read by DecodeNum
+pub fn encode_num(value: i64) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut magnitude = value.unsigned_abs();
+    while magnitude > 0 {
+        data.push((magnitude & 0xff) as u8);
+        magnitude >>= 8;
+    }
+    // the top bit of the last byte holds the sign, add a byte if it is taken
+    match data.last_mut() {
+        Some(last) if *last & 0x80 != 0 => data.push(if value < 0 { 0x80 } else { 0 }),
+        Some(last) if value < 0 => *last |= 0x80,
+        _ => {}
+    }
+    data
+}
+
+/// DecodeNum reads a minimally encoded little endian number whose top bit is
+/// the sign
+fn decode_num(data: &[u8], max_size: usize) -> std::result::Result<i64, ScriptError> {
+    if data.len() > max_size {
+        return Err(ScriptError::InvalidNumber);
+    }
+    let (last, rest) = match data.split_last() {
+        Some(split) => split,
+        None => return Ok(0),
+    };
+    // the last byte may only be empty of value bits when it holds the sign of
+    // a magnitude using the top bit of the previous byte
+    if last & 0x7f == 0 && rest.last().is_none_or(|b| b & 0x80 == 0) {
+        return Err(ScriptError::InvalidNumber);
+    }
+    let mut value: i64 = 0;
+    for (i, b) in data.iter().enumerate() {
+        value |= (*b as i64) << (8 * i);
+    }
+    let sign_bit = 0x80 << (8 * rest.len());
+    if value & sign_bit != 0 {
+        Ok(-(value & !sign_bit))
+    } else {
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// MockChecker accepts a signature when it is "sig" followed by the
+    /// public key, and lock times up to its own
+    struct MockChecker {
+        lock_time: i64,
+    }
+
+    impl SignatureChecker for MockChecker {
+        fn check_sig(&self, signature: &[u8], pub_key: &[u8], _script_code: &Script) -> bool {
+            signature.strip_prefix(b"sig") == Some(pub_key)
+        }
+
+        fn check_lock_time(&self, lock_time: i64) -> bool {
+            lock_time <= self.lock_time
+        }
+    }
+
+    const CHECKER: MockChecker = MockChecker { lock_time: 100 };
+
+    fn key(n: u8) -> Vec<u8> {
+        vec![n; 32]
+    }
+
+    fn sign(pub_key: &[u8]) -> Vec<u8> {
+        [b"sig", pub_key].concat()
+    }
+
+    fn p2sh(redeem_script: &Script) -> Script {
+        Script::new_p2sh(&hash160(&redeem_script.to_bytes().unwrap()))
+    }
+
+    fn htlc() -> (Htlc, Vec<u8>) {
+        let preimage = b"secret".to_vec();
+        let htlc = Htlc {
+            hash: sha256(&preimage),
+            recipient: hash160(&key(1)),
+            refund: hash160(&key(2)),
+            lock_time: 50,
+        };
+        (htlc, preimage)
+    }
+
+    #[test]
+    fn p2pkh() {
+        let script_pubkey = Script::new_p2pkh(&hash160(&key(1)));
+        let script_sig = Script::new_p2pkh_sig(&sign(&key(1)), &key(1));
+        assert_eq!(verify_script(&script_sig, &script_pubkey, &CHECKER), Ok(()));
+
+        let wrong_key = Script::new_p2pkh_sig(&sign(&key(2)), &key(2));
+        assert_eq!(verify_script(&wrong_key, &script_pubkey, &CHECKER), Err(ScriptError::EqualVerify));
+
+        let bad_signature = Script::new_p2pkh_sig(&sign(&key(2)), &key(1));
+        assert_eq!(verify_script(&bad_signature, &script_pubkey, &CHECKER), Err(ScriptError::EvalFalse));
+    }
+
+    #[test]
+    fn script_sig_must_be_push_only() {
+        let script_pubkey = Script::new_p2pkh(&hash160(&key(1)));
+        let script_sig = Script(vec![Op::Push(sign(&key(1))), Op::Push(key(1)), Op::Dup, Op::Drop]);
+        assert_eq!(verify_script(&script_sig, &script_pubkey, &CHECKER), Err(ScriptError::SigPushOnly));
+    }
+
+    #[test]
+    fn p2sh_multisig() {
+        let redeem_script = Script::new_multisig(2, &[key(3), key(1), key(2)]);
+        let script_pubkey = p2sh(&redeem_script);
+
+        let in_order = Script::new_multisig_sig(&[sign(&key(1)), sign(&key(3))], &redeem_script).unwrap();
+        assert_eq!(verify_script(&in_order, &script_pubkey, &CHECKER), Ok(()));
+
+        // signatures must follow the order of the keys in the redeem script
+        let out_of_order = Script::new_multisig_sig(&[sign(&key(3)), sign(&key(1))], &redeem_script).unwrap();
+        assert_eq!(verify_script(&out_of_order, &script_pubkey, &CHECKER), Err(ScriptError::EvalFalse));
+
+        let same_key_twice = Script::new_multisig_sig(&[sign(&key(1)), sign(&key(1))], &redeem_script).unwrap();
+        assert_eq!(verify_script(&same_key_twice, &script_pubkey, &CHECKER), Err(ScriptError::EvalFalse));
+
+        let too_few = Script::new_multisig_sig(&[sign(&key(1))], &redeem_script).unwrap();
+        assert_eq!(verify_script(&too_few, &script_pubkey, &CHECKER), Err(ScriptError::StackUnderflow));
+
+        let other_script = Script::new_multisig(1, &[key(1), key(2)]);
+        let wrong_redeem = Script::new_multisig_sig(&[sign(&key(1))], &other_script).unwrap();
+        assert_eq!(verify_script(&wrong_redeem, &script_pubkey, &CHECKER), Err(ScriptError::EvalFalse));
+    }
+
+    #[test]
+    fn htlc_hash_branch() {
+        let (htlc, preimage) = htlc();
+        let redeem_script = Script::new_htlc(&htlc);
+        let script_pubkey = p2sh(&redeem_script);
+        assert_eq!(redeem_script.htlc(), Some(htlc));
+
+        let script_sig = Script::new_htlc_sig(&sign(&key(1)), &key(1), Some(&preimage), &redeem_script).unwrap();
+        assert_eq!(verify_script(&script_sig, &script_pubkey, &CHECKER), Ok(()));
+
+        let wrong_preimage = Script::new_htlc_sig(&sign(&key(1)), &key(1), Some(b"guess"), &redeem_script).unwrap();
+        assert_eq!(verify_script(&wrong_preimage, &script_pubkey, &CHECKER), Err(ScriptError::EqualVerify));
+
+        let refund_key = Script::new_htlc_sig(&sign(&key(2)), &key(2), Some(&preimage), &redeem_script).unwrap();
+        assert_eq!(verify_script(&refund_key, &script_pubkey, &CHECKER), Err(ScriptError::EqualVerify));
+    }
+
+    #[test]
+    fn htlc_refund_branch() {
+        let (htlc, _) = htlc();
+        let redeem_script = Script::new_htlc(&htlc);
+        let script_pubkey = p2sh(&redeem_script);
+
+        let script_sig = Script::new_htlc_sig(&sign(&key(2)), &key(2), None, &redeem_script).unwrap();
+        assert_eq!(verify_script(&script_sig, &script_pubkey, &CHECKER), Ok(()));
+
+        let early = MockChecker { lock_time: 49 };
+        assert_eq!(verify_script(&script_sig, &script_pubkey, &early), Err(ScriptError::UnsatisfiedLockTime));
+
+        let recipient_key = Script::new_htlc_sig(&sign(&key(1)), &key(1), None, &redeem_script).unwrap();
+        assert_eq!(verify_script(&recipient_key, &script_pubkey, &CHECKER), Err(ScriptError::EqualVerify));
+    }
+
+    #[test]
+    fn conditionals() {
+        let mut stack = Vec::new();
+        let script = Script(vec![
+            Op::Push(vec![1]),
+            Op::Push(Vec::new()),
+            Op::If,
+            Op::Push(vec![2]),
+            Op::Else,
+            Op::Push(vec![1]),
+            Op::If,
+            Op::Push(vec![3]),
+            Op::EndIf,
+            Op::EndIf,
+        ]);
+        assert_eq!(eval(&script, &mut stack, &CHECKER), Ok(()));
+        assert_eq!(stack, vec![vec![1], vec![3]]);
+
+        // an IF in a branch that does not run pops nothing
+        let mut stack = Vec::new();
+        let script = Script(vec![Op::Push(vec![1]), Op::Push(Vec::new()), Op::If, Op::If, Op::EndIf, Op::EndIf]);
+        assert_eq!(eval(&script, &mut stack, &CHECKER), Ok(()));
+        assert_eq!(stack, vec![vec![1]]);
+
+        for ops in [vec![Op::Push(vec![1]), Op::If], vec![Op::Else], vec![Op::EndIf]] {
+            let mut stack = Vec::new();
+            assert_eq!(eval(&Script(ops), &mut stack, &CHECKER), Err(ScriptError::UnbalancedConditional));
+        }
+    }
+
+    #[test]
+    fn op_return() {
+        let mut stack = Vec::new();
+        let script = Script::new_data(b"data");
+        assert!(script.is_unspendable());
+        assert_eq!(eval(&script, &mut stack, &CHECKER), Err(ScriptError::OpReturn));
+    }
+
+    #[test]
+    fn limits() {
+        let mut ops = vec![Op::Push(vec![1])];
+        for _ in 0..MAX_OPS_PER_SCRIPT {
+            ops.extend([Op::Verify, Op::Push(vec![1])]);
+        }
+        assert_eq!(eval(&Script(ops.clone()), &mut Vec::new(), &CHECKER), Ok(()));
+        ops.push(Op::Verify);
+        assert_eq!(eval(&Script(ops), &mut Vec::new(), &CHECKER), Err(ScriptError::OpCount));
+
+        let pushes = vec![Op::Push(vec![1]); MAX_STACK_SIZE];
+        assert_eq!(eval(&Script(pushes.clone()), &mut Vec::new(), &CHECKER), Ok(()));
+        let mut stack = Vec::new();
+        assert_eq!(eval(&Script([pushes, vec![Op::Dup]].concat()), &mut stack, &CHECKER), Err(ScriptError::StackSize));
+
+        let mut keys = vec![Op::Push(Vec::new()), Op::Push(encode_num(0))];
+        keys.push(Op::Push(encode_num(MAX_PUBKEYS_PER_MULTISIG as i64 + 1)));
+        keys.push(Op::CheckMultiSig);
+        assert_eq!(eval(&Script(keys), &mut Vec::new(), &CHECKER), Err(ScriptError::PubKeyCount));
+    }
+
+    #[test]
+    fn numbers() {
+        for value in [0, 1, -1, 127, -127, 128, -128, 255, 256, -256, 0x7fff_ffff, -0x7fff_ffff] {
+            assert_eq!(decode_num(&encode_num(value), MAX_NUM_SIZE), Ok(value));
+        }
+        assert_eq!(encode_num(0), Vec::<u8>::new());
+        assert_eq!(encode_num(128), vec![0x80, 0x00]);
+        assert_eq!(encode_num(-1), vec![0x81]);
+        assert_eq!(decode_num(&encode_num(500_000_000), LOCK_TIME_NUM_SIZE), Ok(500_000_000));
+        assert_eq!(decode_num(&encode_num(0x80_0000_0000 - 1), LOCK_TIME_NUM_SIZE), Ok(0x80_0000_0000 - 1));
+
+        // non minimal encodings and negative zero
+        for data in [vec![0x00], vec![0x80], vec![0x01, 0x00], vec![0x7f, 0x80]] {
+            assert_eq!(decode_num(&data, MAX_NUM_SIZE), Err(ScriptError::InvalidNumber));
+        }
+        assert_eq!(decode_num(&encode_num(0x1_0000_0000), MAX_NUM_SIZE), Err(ScriptError::InvalidNumber));
+    }
+}
@@ -1,14 +1,14 @@
 use std::{collections::{HashMap, HashSet}, io::{Read, Write}, net::{TcpListener, TcpStream}, sync::{Arc, Mutex}, thread, time::Duration};
 
 use failure::format_err;
-use log::{debug, info};
+use log::{debug, error, info};
 use serde::{Deserialize, Serialize};
 
-use crate::{block::Block, errors::Result, transaction::Transaction, utxoset::UTXOSet};
+use crate::{amount::Amount, block::Block, config::Network, errors::{Result, TxError}, monetary, transaction::Transaction, tx::OutPoint, utxoset::UTXOSet};
 
 
 
-const KNOWN_NODE1: &str = "localhost:3000";
+const MAGIC_LEN: usize = 4;
 const CMD_LEN: usize = 12;
 const VERSION: i32 = 1;
 
@@ -16,6 +16,7 @@ const VERSION: i32 = 1;
 pub struct Server {
     node_address: String,
     mining_address: String,
+    network: Network,
     inner: Arc<Mutex<ServerInner>>,
 }
 
@@ -82,11 +83,12 @@ impl Server {
         miner_address: &str,
         utxo: UTXOSet
     ) -> Result<Server> {
-        let mut node_set = HashSet::new();
-        node_set.insert(String::from(KNOWN_NODE1));
+        let network = utxo.blockchain.config().network();
+        let node_set: HashSet<String> = network.seed_nodes().into_iter().collect();
         Ok(Server {
             node_address: String::from("localhost:") + port,
             mining_address: miner_address.to_string(),
+            network,
             inner: Arc::new(Mutex::new(ServerInner {
                 known_nodes: node_set,
                 utxo,
@@ -100,11 +102,12 @@ impl Server {
         let server1 = Server {
             node_address: self.node_address.clone(),
             mining_address: self.mining_address.clone(),
+            network: self.network,
             inner: Arc::clone(&self.inner),
         };
         info!(
-            "Server started at {}, mining address: {}",
-            &self.node_address, &self.mining_address
+            "Server started at {} on {}, mining address: {}",
+            &self.node_address, self.network.name(), &self.mining_address
         );
 
         thread::spawn(move || {
@@ -112,7 +115,10 @@ impl Server {
             if server1.get_best_height()? == -1 {
                 server1.request_blocks()
             } else {
-                server1.send_version(KNOWN_NODE1)
+                for node in server1.network.seed_nodes() {
+                    server1.send_version(&node)?
+                }
+                Ok(())
             }
         });
 
@@ -124,6 +130,7 @@ impl Server {
             let server1 = Server {
                 node_address: self.node_address.clone(),
                 mining_address: self.mining_address.clone(),
+                network: self.network,
                 inner: Arc::clone(&self.inner),
             };
             thread::spawn(move || server1.handle_connection(stream));
@@ -134,7 +141,9 @@ impl Server {
 
     pub fn send_transaction(tx: &Transaction, utxoset: UTXOSet) -> Result<()> {
         let server = Server::new("7000", "", utxoset)?;
-        server.send_tx(KNOWN_NODE1, tx)?;
+        for node in server.network.seed_nodes() {
+            server.send_tx(&node, tx)?;
+        }
         Ok(())
     }
 
@@ -151,7 +160,7 @@ impl Server {
         self.inner.lock().unwrap().known_nodes.clone()
     }
 
-    fn get_block_hashs(&self) -> Vec<String> {
+    fn get_block_hashs(&self) -> Result<Vec<String>> {
         self.inner.lock().unwrap().utxo.blockchain.get_block_hashs()
     }
 
@@ -168,10 +177,7 @@ impl Server {
     }
 
     fn get_mempool_tx(&self, addr: &str) -> Option<Transaction> {
-        match self.inner.lock().unwrap().mempool.get(addr) {
-            Some(tx) => Some(tx.clone()),
-            None => None,
-        }
+        self.inner.lock().unwrap().mempool.get(addr).cloned()
     }
 
     fn get_block(&self, block_hash: &str) -> Result<Block> {
@@ -186,7 +192,7 @@ impl Server {
     /* ------------------------------send commands-------------------------------------- */
     
     fn send_data(&self, addr: &str, data: &[u8]) -> Result<()> {
-        if addr == &self.node_address {
+        if addr == self.node_address {
             return Ok(())
         }
         let mut stream = match TcpStream::connect(addr) {
@@ -197,7 +203,7 @@ impl Server {
             }
         };
 
-        stream.write(data)?;
+        stream.write_all(data)?;
 
         info!("Data send successfully");
         Ok(())
@@ -206,7 +212,7 @@ impl Server {
     fn send_addr(&self, addr: &str) -> Result<()> {
         info!("Send address info to: {}", addr);
         let nodes = self.get_known_nodes();
-        let data = bincode::serialize(&(self.cmd_to_bytes("addr"), nodes))?;
+        let data = bincode::serialize(&(self.network.magic(), self.cmd_to_bytes("addr"), nodes))?;
         self.send_data(addr, &data)
     }
 
@@ -216,7 +222,7 @@ impl Server {
             addr_from: self.node_address.clone(),
             block: b.clone(),
         };
-        let data = bincode::serialize(&(self.cmd_to_bytes("block"), data))?;
+        let data = bincode::serialize(&(self.network.magic(), self.cmd_to_bytes("block"), data))?;
         self.send_data(addr, &data)
     }
 
@@ -230,7 +236,7 @@ impl Server {
             kind: kind.to_string(),
             items,
         };
-        let data = bincode::serialize(&(self.cmd_to_bytes("inv"), data))?;
+        let data = bincode::serialize(&(self.network.magic(), self.cmd_to_bytes("inv"), data))?;
         self.send_data(addr, &data)
     }
 
@@ -240,7 +246,7 @@ impl Server {
             addr_from: self.node_address.clone(),
             transaction: tx.clone(),
         };
-        let data = bincode::serialize(&(self.cmd_to_bytes("tx"), data))?;
+        let data = bincode::serialize(&(self.network.magic(), self.cmd_to_bytes("tx"), data))?;
         self.send_data(addr, &data)
     }
 
@@ -251,7 +257,7 @@ impl Server {
             best_height: self.get_best_height()?,
             version: VERSION,
         };
-        let data = bincode::serialize(&(self.cmd_to_bytes("version"), data))?;
+        let data = bincode::serialize(&(self.network.magic(), self.cmd_to_bytes("version"), data))?;
         self.send_data(addr, &data)
     }
 
@@ -260,7 +266,7 @@ impl Server {
         let data = GetBlocksmsg {
             addr_from: self.node_address.clone(),
         };
-        let data = bincode::serialize(&(self.cmd_to_bytes("getblocks"), data))?;
+        let data = bincode::serialize(&(self.network.magic(), self.cmd_to_bytes("getblocks"), data))?;
         self.send_data(addr, &data)
     }
 
@@ -274,7 +280,7 @@ impl Server {
             kind: kind.to_string(),
             id: id.to_string()
         };
-        let data = bincode::serialize(&(self.cmd_to_bytes("getdata"), data))?;
+        let data = bincode::serialize(&(self.network.magic(), self.cmd_to_bytes("getdata"), data))?;
         self.send_data(addr, &data)
     }
 
@@ -314,16 +320,18 @@ impl Server {
             msg.addr_from,
             msg.block.get_hash()
         );
-        self.add_block(msg.block)?;
+        let block_hash = msg.block.get_hash();
+        if let Err(e) = self.add_block(msg.block) {
+            error!("Rejected block {}: {}", block_hash, e);
+            return Ok(());
+        }
 
         let mut in_transit = self.get_in_transit();
-        if in_transit.len() > 0 {
+        if !in_transit.is_empty() {
             let block_hash = &in_transit[0];
             self.send_get_data(&msg.addr_from, "block", block_hash)?;
             in_transit.remove(0);
             self.replace_in_transit(in_transit);
-        } else {
-            self.utxo_reindex()?;
         }
 
         Ok(())
@@ -331,7 +339,7 @@ impl Server {
 
     fn handle_get_blocks(&self, msg: GetBlocksmsg) -> Result<()> {
         info!("Received get blocks msg: {:#?}", msg);
-        let block_hashs = self.get_block_hashs();
+        let block_hashs = self.get_block_hashs()?;
         self.send_inv(&msg.addr_from, "block", block_hashs)?;
         Ok(())
     }
@@ -350,10 +358,13 @@ impl Server {
 
     fn handle_tx(&self, msg: Txmsg) -> Result<()> {
         info!("Received tx msg: {} {}", msg.addr_from, &msg.transaction.id);
-        self.insert_mempool(msg.transaction.clone());
+        if let Err(e) = self.accept_to_mempool(msg.transaction.clone()) {
+            error!("Rejected transaction {}: {}", msg.transaction.id, e);
+            return Ok(());
+        }
 
         let known_nodes = self.get_known_nodes();
-        if self.node_address == KNOWN_NODE1 {
+        if self.network.seed_nodes().contains(&self.node_address) {
             for node in known_nodes {
                 if node != self.node_address && node != msg.addr_from {
                     self.send_inv(&node, "tx", vec![msg.transaction.id.clone()])?;
@@ -362,13 +373,23 @@ impl Server {
         } else {
             let mut mempool = self.get_mempool();
             debug!("Current mempool: {:#?}", &mempool);
-            if mempool.len() >= 1 && !self.mining_address.is_empty() {
+            if !mempool.is_empty() && !self.mining_address.is_empty() {
                 loop {
                     let mut txs = Vec::new();
+                    let mut fees = Amount::ZERO;
+                    let mut spent = HashSet::new();
 
-                    for (_, tx) in &mempool {
-                        if self.verify_tx(tx)? {
-                            txs.push(tx.clone());
+                    for tx in mempool.values() {
+                        if tx.vin.iter().any(|vin| spent.contains(&OutPoint::new(&vin.txid, vin.vout))) {
+                            continue;
+                        }
+                        match self.validate_tx(tx) {
+                            Ok(fee) => {
+                                fees = fees.checked_add(fee).ok_or_else(|| format_err!("Fees overflow"))?;
+                                spent.extend(tx.vin.iter().map(|vin| OutPoint::new(&vin.txid, vin.vout)));
+                                txs.push(tx.clone());
+                            }
+                            Err(e) => info!("Skip transaction {}: {}", tx.id, e),
                         }
                     }
 
@@ -376,15 +397,18 @@ impl Server {
                         return Ok(());
                     }
 
-                    let cbtx = Transaction::new_coinbase(self.mining_address.clone(), String::new())?;
-                    txs.push(cbtx);
+                    let height = self.get_best_height()? + 1;
+                    let reward = monetary::block_subsidy(height, self.network)
+                        .checked_add(fees)
+                        .ok_or_else(|| format_err!("Block reward overflow"))?;
+                    let cbtx = Transaction::new_coinbase(self.mining_address.clone(), String::new(), height, reward)?;
+                    txs.insert(0, cbtx);
 
                     for tx in &txs {
                         mempool.remove(&tx.id);
                     }
 
                     let new_block = self.mine_block(txs)?;
-                    self.utxo_reindex()?;
 
                     for node in self.get_known_nodes() {
                         if node != self.node_address {
@@ -392,7 +416,7 @@ impl Server {
                         }
                     }
 
-                    if mempool.len() == 0 {
+                    if mempool.is_empty() {
                         break;
                     }
                 }
@@ -456,28 +480,68 @@ impl Server {
     }
 
     fn add_block(&self, block: Block) -> Result<()> {
-        self.inner.lock().unwrap().utxo.blockchain.add_block(block)
+        let mut inner = self.inner.lock().unwrap();
+        let update = inner.utxo.add_block(block)?;
+        for b in &update.disconnected {
+            for tx in b.get_transaction() {
+                if !tx.is_coinbase() {
+                    inner.mempool.insert(tx.id.clone(), tx.clone());
+                }
+            }
+        }
+        let mut spent = HashSet::new();
+        for b in &update.connected {
+            for tx in b.get_transaction() {
+                inner.mempool.remove(&tx.id);
+                spent.extend(tx.vin.iter().map(|vin| OutPoint::new(&vin.txid, vin.vout)));
+            }
+        }
+        // transactions spending the same outputs as the new blocks can never confirm
+        inner
+            .mempool
+            .retain(|_, tx| !tx.vin.iter().any(|vin| spent.contains(&OutPoint::new(&vin.txid, vin.vout))));
+        Ok(())
     }
 
     fn mine_block(&self, txs: Vec<Transaction>) -> Result<Block> {
-        self.inner.lock().unwrap().utxo.blockchain.mine_block(txs)
+        let mut inner = self.inner.lock().unwrap();
+        let block = inner.utxo.blockchain.mine_block(txs)?;
+        inner.utxo.update(&block)?;
+        Ok(block)
     }
 
     fn node_is_known(&self, addr: &str) -> bool {
-        self.inner.lock().unwrap().known_nodes.get(addr).is_some()
+        self.inner.lock().unwrap().known_nodes.contains(addr)
     }
 
-    fn verify_tx(&self, tx: &Transaction) -> Result<bool> {
-        self.inner
-            .lock()
-            .unwrap()
-            .utxo
-            .blockchain
-            .verify_transaction(tx)
+    fn validate_tx(&self, tx: &Transaction) -> Result<Amount> {
+        self.inner.lock().unwrap().utxo.validate_transaction(tx)
     }
 
-    fn insert_mempool(&self, tx: Transaction) {
-        self.inner.lock().unwrap().mempool.insert(tx.id.clone(), tx);
+    /// AcceptToMempool adds a transaction to the mempool when it is valid
+    /// against the UTXO set and spends no output another mempool transaction spends
+    fn accept_to_mempool(&self, tx: Transaction) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.mempool.contains_key(&tx.id) {
+            return Ok(());
+        }
+        for vin in &tx.vin {
+            let conflict = inner.mempool.values().find(|other| {
+                other.vin.iter().any(|o| o.txid == vin.txid && o.vout == vin.vout)
+            });
+            if let Some(conflict) = conflict {
+                return Err(TxError::MempoolConflict {
+                    txid: tx.id.clone(),
+                    prev_txid: vin.txid.clone(),
+                    vout: vin.vout,
+                    conflict: conflict.id.clone(),
+                }
+                .into());
+            }
+        }
+        inner.utxo.validate_transaction(&tx)?;
+        inner.mempool.insert(tx.id.clone(), tx);
+        Ok(())
     }
 
     fn replace_in_transit(&self, hashs: Vec<String>) {
@@ -485,10 +549,6 @@ impl Server {
         bit.clone_from(&hashs);
     }
 
-    fn utxo_reindex(&self) -> Result<()> {
-        self.inner.lock().unwrap().utxo.reindex()
-    }
-
     fn clear_mempool(&self) {
         self.inner.lock().unwrap().mempool.clear()
     }
@@ -503,11 +563,17 @@ impl Server {
 
     // TODO) send helper functions to a utils file
     fn bytes_to_cmd(&self, bytes: &[u8]) -> Result<Message> {
+        if bytes.len() < MAGIC_LEN + CMD_LEN {
+            return Err(format_err!("Message is too short"));
+        }
+        if bytes[..MAGIC_LEN] != self.network.magic() {
+            return Err(format_err!("Message is not from a {} node", self.network.name()));
+        }
         let mut cmd = Vec::new();
-        let cmd_bytes = &bytes[..CMD_LEN];
-        let data = &bytes[CMD_LEN..];
+        let cmd_bytes = &bytes[MAGIC_LEN..MAGIC_LEN + CMD_LEN];
+        let data = &bytes[MAGIC_LEN + CMD_LEN..];
         for b in cmd_bytes {
-            if 0 as u8 != *b {
+            if 0_u8 != *b {
                 cmd.push(*b);
             }
         }
@@ -1,22 +1,101 @@
 use std::collections::HashMap;
 
+use bincode::Options;
 use crypto::{digest::Digest, ed25519, sha2::Sha256};
 use failure::format_err;
 use serde::{Deserialize, Serialize};
-use log::error;
-use crate::{errors::Result, tx::{TXInput, TXOutput}, utxoset::UTXOSet, wallet::{hash_pub_key, Wallet}};
+use log::{debug, error};
+use crate::{
+    amount::Amount,
+    config::Network,
+    errors::Result,
+    script::{self, Op, Script, SignatureChecker},
+    tx::{TXInput, TXOutput},
+    utxoset::UTXOSet,
+    wallet::{hash_pub_key, script_address, Wallet},
+};
+
+/// Lock times below the threshold are block heights, the others unix times in seconds
+pub const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+/// Sequence of an input without relative lock time. The lock time of a
+/// transaction whose inputs all have it is not enforced
+pub const SEQUENCE_FINAL: u32 = 0xffff_ffff;
+/// Set on the sequence of an input without relative lock time
+pub const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+/// Set on a relative lock time counting units of 512 seconds instead of blocks
+pub const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+/// Bits of the sequence holding the relative lock time
+pub const SEQUENCE_LOCKTIME_MASK: u32 = 0xffff;
+/// Relative lock times in seconds are shifted by this many bits
+pub const SEQUENCE_LOCKTIME_GRANULARITY: u32 = 9;
+
+/// Version byte prefixing the raw encoding of transactions
+const RAW_TX_VERSION: u8 = 1;
+
+/// Signatures commit to every input and output
+pub const SIGHASH_ALL: u8 = 0x01;
+/// Signatures commit to no output, any output may be changed
+pub const SIGHASH_NONE: u8 = 0x02;
+/// Signatures commit to the output at the index of the signed input only
+pub const SIGHASH_SINGLE: u8 = 0x03;
+/// Combined with another type, signatures commit to the signed input only,
+/// so anyone may add inputs
+pub const SIGHASH_ANYONECANPAY: u8 = 0x80;
+
+/// SighashFromName parses a sighash type such as "ALL" or "SINGLE|ANYONECANPAY"
+pub fn sighash_from_name(name: &str) -> Result<u8> {
+    let (base, anyone_can_pay) = match name.strip_suffix("|ANYONECANPAY") {
+        Some(base) => (base, SIGHASH_ANYONECANPAY),
+        None => (name, 0),
+    };
+    let base = match base {
+        "ALL" => SIGHASH_ALL,
+        "NONE" => SIGHASH_NONE,
+        "SINGLE" => SIGHASH_SINGLE,
+        _ => return Err(format_err!("Unknown sighash type {}", name)),
+    };
+    Ok(base | anyone_can_pay)
+}
+
+/// TxOptions are the settings of a transaction built by NewUTXO
+#[derive(Debug, Clone)]
+pub struct TxOptions {
+    /// Fee paid to the miner
+    pub fee: Amount,
+    /// LockTime of the transaction
+    pub lock_time: u32,
+    /// Sequence of every input
+    pub sequence: u32,
+    /// Data carried by an unspendable output
+    pub data: Option<Vec<u8>>,
+}
+
+impl Default for TxOptions {
+    fn default() -> Self {
+        TxOptions {
+            fee: Amount::ZERO,
+            lock_time: 0,
+            sequence: SEQUENCE_FINAL,
+            data: None,
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Transaction {
     pub id: String,
     pub vin: Vec<TXInput>,
     pub vout: Vec<TXOutput>,
+    /// LockTime is the first block height or time at which the transaction
+    /// may be mined, minus one
+    pub lock_time: u32,
 }
 
 impl Transaction {
-    /// NewCoinbase creates a new coinbase
-    pub fn new_coinbase(to: String, mut data: String) -> Result<Transaction> {
-        if data == String::from("") {
+    /// NewCoinbase creates a new coinbase for the block at the given height
+    /// paying the reward to the miner
+    pub fn new_coinbase(to: String, mut data: String, height: i32, reward: Amount) -> Result<Transaction> {
+        if data.is_empty() {
             data += &format!("Reward to {}", to);
         }
         let mut tx = Transaction {
@@ -24,24 +103,112 @@ impl Transaction {
             vin: vec![TXInput {
                 txid: String::new(),
                 vout: -1,
-                signature: Vec::new(),
-                pub_key: Vec::from(data.as_bytes()),
+                script_sig: Script::new(vec![
+                    Op::Push(height.to_le_bytes().to_vec()),
+                    Op::Push(data.into_bytes()),
+                ]),
+                sequence: SEQUENCE_FINAL,
             }],
-            vout: vec![TXOutput::new(100, to)?],
+            vout: vec![TXOutput::new(reward, to)?],
+            lock_time: 0,
         };
         tx.id = tx.hash()?;
         Ok(tx)
     }
 
-    /// NewUTXO creates a new transaction
-    pub fn new_utxo(wallet: &Wallet, to: &str, amount: i32, bc: &UTXOSet) -> Result<Transaction> {
-        let mut vin = Vec::new();
-
+    /// NewUTXO creates a new transaction paying amount to the address, with
+    /// the fee, locks and data of the options
+    pub fn new_utxo(wallet: &Wallet, to: &str, amount: Amount, options: &TxOptions, bc: &UTXOSet) -> Result<Transaction> {
         let mut pub_key_hash = wallet.public_key.clone();
         hash_pub_key(&mut pub_key_hash);
+        let from = Script::new_p2pkh(&pub_key_hash);
+        let change_address = wallet.get_address(bc.blockchain.config().network());
 
-        let acc_v = bc.find_spendable_outputs(&pub_key_hash, amount)?;
-        if acc_v.0 < amount {
+        let mut tx = Transaction::new_unsigned(&from, change_address, to, amount, options.fee, bc)?;
+        tx.lock_time = options.lock_time;
+        for vin in &mut tx.vin {
+            vin.sequence = options.sequence;
+        }
+        if let Some(data) = &options.data {
+            tx.vout.insert(1, TXOutput::new_data(data));
+        }
+        tx.id = tx.hash()?;
+        bc.blockchain.sign_transaction(&mut tx, wallet, SIGHASH_ALL)?;
+        Ok(tx)
+    }
+
+    /// NewMultisig creates a transaction spending outputs of a multisig redeem
+    /// script. Its inputs hold empty signature slots the key owners fill in
+    /// with Sign
+    pub fn new_multisig(redeem_script: &Script, to: &str, amount: Amount, fee: Amount, bc: &UTXOSet) -> Result<Transaction> {
+        let (_, pub_keys) = redeem_script
+            .multisig_keys()
+            .ok_or_else(|| format_err!("{} is not a multisig redeem script", redeem_script))?;
+        let from = Script::new_p2sh(&script::hash160(&redeem_script.to_bytes()?));
+        let change_address = script_address(redeem_script, bc.blockchain.config().network())?;
+
+        let mut tx = Transaction::new_unsigned(&from, change_address, to, amount, fee, bc)?;
+        let script_sig = Script::new_multisig_sig(&vec![Vec::new(); pub_keys.len()], redeem_script)?;
+        for vin in &mut tx.vin {
+            vin.script_sig = script_sig.clone();
+        }
+        Ok(tx)
+    }
+
+    /// NewHTLCSpend creates a transaction moving every output of an HTLC to the
+    /// wallet, minus the fee. The recipient spends with the preimage, the
+    /// refund key without it once the lock time has passed
+    pub fn new_htlc_spend(
+        redeem_script: &Script,
+        wallet: &Wallet,
+        preimage: Option<&[u8]>,
+        fee: Amount,
+        bc: &UTXOSet,
+    ) -> Result<Transaction> {
+        let htlc = redeem_script
+            .htlc()
+            .ok_or_else(|| format_err!("{} is not an HTLC redeem script", redeem_script))?;
+        let from = Script::new_p2sh(&script::hash160(&redeem_script.to_bytes()?));
+        let (balance, _) = bc.get_balance(&from)?;
+        let amount = balance
+            .checked_sub(fee)
+            .filter(|amount| *amount > Amount::ZERO)
+            .ok_or_else(|| format_err!("HTLC balance {} does not cover the fee", balance))?;
+        let address = wallet.get_address(bc.blockchain.config().network());
+
+        let mut tx = Transaction::new_unsigned(&from, address.clone(), &address, amount, fee, bc)?;
+        if preimage.is_none() {
+            tx.lock_time = htlc.lock_time;
+            for vin in &mut tx.vin {
+                vin.sequence = SEQUENCE_FINAL - 1;
+            }
+            tx.id = tx.hash()?;
+        }
+        for in_id in 0..tx.vin.len() {
+            let signature = tx.sign_input(in_id, redeem_script, SIGHASH_ALL, bc.blockchain.config().network(), wallet)?;
+            tx.vin[in_id].script_sig = Script::new_htlc_sig(&signature, &wallet.public_key, preimage, redeem_script)?;
+        }
+        Ok(tx)
+    }
+
+    /// NewUnsigned creates a transaction spending outputs locked by from,
+    /// paying amount to the address, fee to the miner and the rest back to
+    /// the change address
+    pub fn new_unsigned(
+        from: &Script,
+        change_address: String,
+        to: &str,
+        amount: Amount,
+        fee: Amount,
+        bc: &UTXOSet,
+    ) -> Result<Transaction> {
+        let mut vin = Vec::new();
+
+        let total = amount
+            .checked_add(fee)
+            .ok_or_else(|| format_err!("Amount and fee overflow"))?;
+        let acc_v = bc.find_spendable_outputs(from, total)?;
+        if acc_v.0 < total {
             error!("Not Enough balance");
             return Err(format_err!("Not Enough balance: current balance {}", acc_v.0));
         }
@@ -51,8 +218,8 @@ impl Transaction {
                 let input = TXInput {
                     txid: tx.0.clone(),
                     vout: out,
-                    signature: Vec::new(),
-                    pub_key: wallet.public_key.clone(),
+                    script_sig: Script::default(),
+                    sequence: SEQUENCE_FINAL,
                 };
                 vin.push(input);
             }
@@ -65,11 +232,11 @@ impl Transaction {
             )?
         ];
 
-        if acc_v.0 > amount {
+        if let Some(change) = acc_v.0.checked_sub(total).filter(|change| *change > Amount::ZERO) {
             vout.push(
                 TXOutput::new(
-                    acc_v.0 - amount,
-                    wallet.get_address()
+                    change,
+                    change_address
                 )?
             )
         }
@@ -79,9 +246,9 @@ impl Transaction {
             id: String::new(),
             vin,
             vout,
+            lock_time: 0,
         };
         tx.id = tx.hash()?;
-        bc.blockchain.sign_transaction(&mut tx, &wallet.private_key)?;
         Ok(tx)
     }
 
@@ -90,76 +257,261 @@ impl Transaction {
         self.vin.len() == 1 && self.vin[0].txid.is_empty() && self.vin[0].vout == -1
     }
 
-    // TODO) Join sign & verify functionalities in a single function in utils
+    /// IsFinal checks that the lock time of the transaction lets it into a
+    /// block at height whose previous blocks have the median time, in seconds
+    pub fn is_final(&self, height: i32, median_time: u64) -> bool {
+        if self.lock_time == 0 || self.vin.iter().all(|vin| vin.sequence == SEQUENCE_FINAL) {
+            return true;
+        }
+        let limit = if self.lock_time < LOCKTIME_THRESHOLD {
+            height as u64
+        } else {
+            median_time
+        };
+        (self.lock_time as u64) < limit
+    }
+
+    /// CoinbaseHeight returns the block height committed to by a coinbase
+    pub fn coinbase_height(&self) -> Option<i32> {
+        if !self.is_coinbase() {
+            return None;
+        }
+        match self.vin[0].script_sig.0.first() {
+            Some(Op::Push(data)) if data.len() == 4 => {
+                let mut height = [0u8; 4];
+                height.copy_from_slice(data);
+                Some(i32::from_le_bytes(height))
+            }
+            _ => None,
+        }
+    }
+
+    /// Sign unlocks the inputs spending P2PKH outputs of the wallet, and adds
+    /// the signature of the wallet to the multisig inputs it is a signer of.
+    /// Other inputs are left untouched. The sighash type selects what the
+    /// signatures commit to, and they are only valid on the given network
     pub fn sign(
         &mut self,
-        private_key: &[u8],
+        wallet: &Wallet,
         prev_txs: HashMap<String, Transaction>,
+        sighash_type: u8,
+        network: Network,
     ) -> Result<()> {
         if self.is_coinbase() {
             return Ok(());
         }
 
-        for vin in &self.vin {
-            if prev_txs.get(&vin.txid).unwrap().id.is_empty() {
-                return Err(format_err!("ERROR: Previous transaction is not correct"));
+        let prev_outputs = self.prev_outputs(&prev_txs)?;
+        let mut pub_key_hash = wallet.public_key.clone();
+        hash_pub_key(&mut pub_key_hash);
+
+        for (in_id, prev_output) in prev_outputs.iter().enumerate() {
+            let script_pubkey = &prev_output.script_pubkey;
+            if script_pubkey.p2pkh_hash() == Some(&pub_key_hash[..]) {
+                let signature = self.sign_input(in_id, script_pubkey, sighash_type, network, wallet)?;
+                self.vin[in_id].script_sig = Script::new_p2pkh_sig(&signature, &wallet.public_key);
+            } else if let Some(script_hash) = script_pubkey.p2sh_hash() {
+                self.sign_multisig(in_id, script_hash, sighash_type, network, wallet)?;
             }
         }
+        Ok(())
+    }
 
-        let mut tx_copy = self.trim_copy();
-
-        for in_id in 0..tx_copy.vin.len() {
-            let prev_tx = prev_txs.get(&tx_copy.vin[in_id].txid).unwrap();
-            tx_copy.vin[in_id].signature.clear();
-            tx_copy.vin[in_id].pub_key = prev_tx.vout[tx_copy.vin[in_id].vout as usize]
-                .pub_key_hash
-                .clone();
-            tx_copy.id = tx_copy.hash()?;
-            tx_copy.vin[in_id].pub_key = Vec::new();
-            let signature =
-                ed25519::signature(tx_copy.id.as_bytes(), private_key);
-            self.vin[in_id].signature = signature.to_vec();
+    /// SignMultisig fills the signature slot of the wallet in a multisig
+    /// input. Once enough slots are filled, the empty ones are dropped so the
+    /// input unlocks its output
+    fn sign_multisig(
+        &mut self,
+        in_id: usize,
+        script_hash: &[u8],
+        sighash_type: u8,
+        network: Network,
+        wallet: &Wallet,
+    ) -> Result<()> {
+        let (redeem_script, mut slots) = match self.vin[in_id].script_sig.redeem_script() {
+            Some(redeem) if script::hash160(&redeem.0.to_bytes()?) == script_hash => redeem,
+            _ => return Ok(()),
+        };
+        let (m, pub_keys) = match redeem_script.multisig_keys() {
+            Some(multisig) => multisig,
+            None => return Ok(()),
+        };
+        let position = match pub_keys.iter().position(|key| *key == &wallet.public_key[..]) {
+            Some(position) => position,
+            // an input with fewer slots than keys is already complete
+            None => return Ok(()),
+        };
+        if slots.len() != pub_keys.len() {
+            return Ok(());
         }
+
+        slots[position] = self.sign_input(in_id, &redeem_script, sighash_type, network, wallet)?;
+        let signatures: Vec<Vec<u8>> = slots.iter().filter(|slot| !slot.is_empty()).cloned().collect();
+        self.vin[in_id].script_sig = if signatures.len() >= m {
+            Script::new_multisig_sig(&signatures[..m], &redeem_script)?
+        } else {
+            Script::new_multisig_sig(&slots, &redeem_script)?
+        };
         Ok(())
     }
 
-    pub fn verify(&self, prev_txs: HashMap<String, Transaction>) -> Result<bool> {
+    /// Verify runs the script_sig of every input against the script_pubkey of
+    /// the output it spends, given in input order. Signatures made for another
+    /// network are rejected
+    pub fn verify(&self, prev_outputs: &[TXOutput], network: Network) -> Result<bool> {
         if self.is_coinbase() {
             return Ok(true);
         }
+        if prev_outputs.len() != self.vin.len() {
+            return Err(format_err!(
+                "Transaction {} has {} inputs but {} spent outputs",
+                self.id,
+                self.vin.len(),
+                prev_outputs.len()
+            ));
+        }
 
+        for (in_id, prev_output) in prev_outputs.iter().enumerate() {
+            let checker = TransactionSignatureChecker { tx: self, input: in_id, network };
+            if let Err(e) = script::verify_script(&self.vin[in_id].script_sig, &prev_output.script_pubkey, &checker) {
+                debug!("Input {} of transaction {} fails its script: {}", in_id, self.id, e);
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// PrevOutputs returns the outputs spent by the inputs, in input order
+    pub fn prev_outputs(&self, prev_txs: &HashMap<String, Transaction>) -> Result<Vec<TXOutput>> {
+        let mut prev_outputs = Vec::new();
         for vin in &self.vin {
-            if prev_txs.get(&vin.txid).unwrap().id.is_empty() {
-                return Err(format_err!("ERROR: Previous transaction is not correct"));
+            match prev_txs.get(&vin.txid) {
+                Some(prev_tx) if !prev_tx.id.is_empty() && vin.vout >= 0
+                    && (vin.vout as usize) < prev_tx.vout.len() => {
+                    prev_outputs.push(prev_tx.vout[vin.vout as usize].clone());
+                }
+                _ => return Err(format_err!("ERROR: Previous transaction is not correct")),
             }
         }
+        Ok(prev_outputs)
+    }
+
+    /// SignInput returns the signature of an input by the wallet, followed by
+    /// its sighash type
+    pub fn sign_input(
+        &self,
+        input: usize,
+        script_code: &Script,
+        sighash_type: u8,
+        network: Network,
+        wallet: &Wallet,
+    ) -> Result<Vec<u8>> {
+        let digest = self.signature_hash(input, script_code, sighash_type, network)?;
+        let mut signature = ed25519::signature(&digest, &wallet.private_key).to_vec();
+        signature.push(sighash_type);
+        Ok(signature)
+    }
 
+    /// SignatureHash returns the digest an input signs: the transaction without
+    /// unlocking scripts, with the script code in place of the signed input's.
+    /// NONE drops the outputs, SINGLE keeps the one at the index of the input
+    /// and ANYONECANPAY drops the other inputs. The chain id of the network is
+    /// appended so the digest differs between networks
+    pub fn signature_hash(&self, input: usize, script_code: &Script, sighash_type: u8, network: Network) -> Result<Vec<u8>> {
         let mut tx_copy = self.trim_copy();
+        tx_copy.id = String::new();
+        tx_copy.vin[input].script_sig = script_code.clone();
 
-        for in_id in 0..tx_copy.vin.len() {
-            let prev_tx = prev_txs.get(&tx_copy.vin[in_id].txid).unwrap();
-            tx_copy.vin[in_id].signature.clear();
-            tx_copy.vin[in_id].pub_key = prev_tx.vout[tx_copy.vin[in_id].vout as usize]
-                .pub_key_hash
-                .clone();
-            tx_copy.id = tx_copy.hash()?;
-            tx_copy.vin[in_id].pub_key = Vec::new();
-            
-            if !ed25519::verify(
-                &tx_copy.id.as_bytes(),
-                &self.vin[in_id].pub_key, 
-                &self.vin[in_id].signature,
-            ) {
-                return Ok(false);
+        match sighash_type & !SIGHASH_ANYONECANPAY {
+            SIGHASH_ALL => {}
+            SIGHASH_NONE => tx_copy.vout.clear(),
+            SIGHASH_SINGLE => {
+                if input >= tx_copy.vout.len() {
+                    return Err(format_err!("Input {} has no output to sign with SIGHASH_SINGLE", input));
+                }
+                tx_copy.vout.truncate(input + 1);
+                for out in &mut tx_copy.vout[..input] {
+                    out.value = Amount::ZERO;
+                    out.script_pubkey = Script::default();
+                }
+            }
+            _ => return Err(format_err!("Unknown sighash type {:#04x}", sighash_type)),
+        }
+        if sighash_type & !SIGHASH_ANYONECANPAY != SIGHASH_ALL {
+            // the other inputs may be replaced, so may their sequences
+            for (in_id, vin) in tx_copy.vin.iter_mut().enumerate() {
+                if in_id != input {
+                    vin.sequence = 0;
+                }
             }
         }
+        if sighash_type & SIGHASH_ANYONECANPAY != 0 {
+            tx_copy.vin = vec![tx_copy.vin.swap_remove(input)];
+        }
 
-        Ok(true)
+        let data = bincode::serialize(&(tx_copy, sighash_type, network.chain_id()))?;
+        let mut hasher = Sha256::new();
+        hasher.input(&data[..]);
+        let mut digest = vec![0u8; hasher.output_bytes()];
+        hasher.result(&mut digest);
+        Ok(digest)
     }
 
-    fn hash(&mut self) -> Result<String> {
-        self.id = String::new();
-        let data = bincode::serialize(self)?;
+    /// Hash returns the id the transaction should have for its contents.
+    /// Unlocking scripts are added after the id is set, so they are not
+    /// covered, except for the coinbase one carrying the block height
+    pub fn hash(&self) -> Result<String> {
+        let mut copy = self.clone();
+        copy.id = String::new();
+        if !self.is_coinbase() {
+            for vin in &mut copy.vin {
+                vin.script_sig = Script::default();
+            }
+        }
+        let data = bincode::serialize(&copy)?;
+        let mut hasher = Sha256::new();
+        hasher.input(&data[..]);
+        Ok(hasher.result_str())
+    }
+
+    /// ToHex returns the raw encoding of the transaction: the version byte
+    /// followed by the bincode inputs, outputs and lock time. The id is left
+    /// out since it is derived from them
+    pub fn to_hex(&self) -> Result<String> {
+        let mut data = vec![RAW_TX_VERSION];
+        data.extend(bincode::serialize(&(&self.vin, &self.vout, self.lock_time))?);
+        Ok(hex::encode(data))
+    }
+
+    /// FromHex decodes a raw transaction and recomputes its id
+    pub fn from_hex(data: &str) -> Result<Transaction> {
+        let data = hex::decode(data)?;
+        match data.split_first() {
+            Some((&RAW_TX_VERSION, body)) => {
+                let (vin, vout, lock_time) = bincode::DefaultOptions::new()
+                    .with_fixint_encoding()
+                    .deserialize(body)?;
+                let mut tx = Transaction {
+                    id: String::new(),
+                    vin,
+                    vout,
+                    lock_time,
+                };
+                tx.id = tx.hash()?;
+                Ok(tx)
+            }
+            Some((version, _)) => Err(format_err!("Unknown raw transaction version {}", version)),
+            None => Err(format_err!("Empty raw transaction")),
+        }
+    }
+
+    /// WitnessHash returns the hash of the whole transaction, unlocking
+    /// scripts included. Unlike the id it changes when signatures are re-encoded
+    pub fn witness_hash(&self) -> Result<String> {
+        let mut copy = self.clone();
+        copy.id = String::new();
+        let data = bincode::serialize(&copy)?;
         let mut hasher = Sha256::new();
         hasher.input(&data[..]);
         Ok(hasher.result_str())
@@ -172,16 +524,16 @@ impl Transaction {
         for v in &self.vin {
             vin.push(TXInput {
                 txid: v.txid.clone(),
-                vout: v.vout.clone(),
-                signature: Vec::new(),
-                pub_key: Vec::new(),
+                vout: v.vout,
+                script_sig: Script::default(),
+                sequence: v.sequence,
             })
         }
 
         for v in &self.vout {
             vout.push(TXOutput {
                 value: v.value,
-                pub_key_hash: v.pub_key_hash.clone(),
+                script_pubkey: v.script_pubkey.clone(),
             })
         }
 
@@ -189,7 +541,179 @@ impl Transaction {
             id: self.id.clone(),
             vin,
             vout,
+            lock_time: self.lock_time,
         }
     }
 }
 
+/// TransactionSignatureChecker checks the signatures of an input of a transaction
+struct TransactionSignatureChecker<'a> {
+    tx: &'a Transaction,
+    input: usize,
+    network: Network,
+}
+
+impl SignatureChecker for TransactionSignatureChecker<'_> {
+    /// CheckSig verifies an ed25519 signature followed by its sighash type
+    fn check_sig(&self, signature: &[u8], pub_key: &[u8], script_code: &Script) -> bool {
+        let (sighash_type, signature) = match signature.split_last() {
+            Some((sighash_type, signature)) if signature.len() == 64 => (*sighash_type, signature),
+            _ => return false,
+        };
+        if pub_key.len() != 32 {
+            return false;
+        }
+        match self.tx.signature_hash(self.input, script_code, sighash_type, self.network) {
+            Ok(digest) => ed25519::verify(&digest, pub_key, signature),
+            Err(_) => false,
+        }
+    }
+
+    /// CheckLockTime checks that the lock time of the transaction is enforced
+    /// and at least lock_time, both counting heights or both times
+    fn check_lock_time(&self, lock_time: i64) -> bool {
+        let tx_lock_time = self.tx.lock_time as i64;
+        let threshold = LOCKTIME_THRESHOLD as i64;
+        (lock_time < threshold) == (tx_lock_time < threshold)
+            && lock_time <= tx_lock_time
+            && self.tx.vin[self.input].sequence != SEQUENCE_FINAL
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wallet(seed: u8) -> Wallet {
+        let (private_key, public_key) = ed25519::keypair(&[seed; 32]);
+        Wallet {
+            private_key: private_key.to_vec(),
+            public_key: public_key.to_vec(),
+        }
+    }
+
+    fn input(n: u8) -> TXInput {
+        TXInput {
+            txid: hex::encode([n; 32]),
+            vout: 0,
+            script_sig: Script::default(),
+            sequence: SEQUENCE_FINAL,
+        }
+    }
+
+    fn output(n: u8) -> TXOutput {
+        TXOutput {
+            value: Amount::from_base_units(n as u64),
+            script_pubkey: Script::new_p2pkh(&[n; 20]),
+        }
+    }
+
+    fn transaction() -> Transaction {
+        Transaction {
+            id: String::new(),
+            vin: vec![input(1), input(2), input(3)],
+            vout: vec![output(1), output(2)],
+            lock_time: 0,
+        }
+    }
+
+    fn digest(tx: &Transaction, input: usize, sighash_type: u8) -> Vec<u8> {
+        tx.signature_hash(input, &Script::new_p2pkh(&[0; 20]), sighash_type, Network::Regtest)
+            .unwrap()
+    }
+
+    /// Commits tells whether the digest of the input changes when the
+    /// transaction is modified
+    fn commits(input: usize, sighash_type: u8, modify: impl Fn(&mut Transaction)) -> bool {
+        let tx = transaction();
+        let mut modified = tx.clone();
+        modify(&mut modified);
+        digest(&tx, input, sighash_type) != digest(&modified, input, sighash_type)
+    }
+
+    #[test]
+    fn sighash_all() {
+        assert!(commits(0, SIGHASH_ALL, |tx| tx.vout[1].value = Amount::ZERO));
+        assert!(commits(0, SIGHASH_ALL, |tx| tx.vout.push(output(3))));
+        assert!(commits(0, SIGHASH_ALL, |tx| tx.vin[1].sequence = 0));
+        assert!(commits(0, SIGHASH_ALL, |tx| tx.vin.push(input(4))));
+        assert!(commits(0, SIGHASH_ALL, |tx| tx.lock_time = 1));
+        assert!(!commits(0, SIGHASH_ALL, |tx| tx.vin[1].script_sig = Script::new_data(b"sig")));
+    }
+
+    #[test]
+    fn sighash_none() {
+        assert!(!commits(0, SIGHASH_NONE, |tx| tx.vout[0].value = Amount::ZERO));
+        assert!(!commits(0, SIGHASH_NONE, |tx| tx.vout.clear()));
+        assert!(!commits(0, SIGHASH_NONE, |tx| tx.vin[1].sequence = 0));
+        assert!(commits(0, SIGHASH_NONE, |tx| tx.vin[0].sequence = 0));
+        assert!(commits(0, SIGHASH_NONE, |tx| tx.vin.push(input(4))));
+    }
+
+    #[test]
+    fn sighash_single() {
+        assert!(commits(1, SIGHASH_SINGLE, |tx| tx.vout[1].value = Amount::ZERO));
+        assert!(!commits(1, SIGHASH_SINGLE, |tx| tx.vout[0].value = Amount::ZERO));
+        assert!(!commits(1, SIGHASH_SINGLE, |tx| tx.vout.push(output(3))));
+        assert!(!commits(1, SIGHASH_SINGLE, |tx| tx.vin[0].sequence = 0));
+        assert!(commits(1, SIGHASH_SINGLE, |tx| tx.vin[0].txid = hex::encode([9; 32])));
+        // the third input has no output at its index
+        let tx = transaction();
+        assert!(tx.signature_hash(2, &Script::default(), SIGHASH_SINGLE, Network::Regtest).is_err());
+    }
+
+    #[test]
+    fn sighash_anyone_can_pay() {
+        let all = SIGHASH_ALL | SIGHASH_ANYONECANPAY;
+        assert!(!commits(1, all, |tx| tx.vin.push(input(4))));
+        assert!(!commits(1, all, |tx| tx.vin.truncate(2)));
+        assert!(commits(1, all, |tx| tx.vout[0].value = Amount::ZERO));
+
+        let single = SIGHASH_SINGLE | SIGHASH_ANYONECANPAY;
+        assert!(!commits(1, single, |tx| tx.vin.truncate(2)));
+        assert!(!commits(1, single, |tx| tx.vout.push(output(3))));
+        assert!(commits(1, single, |tx| tx.vout[1].value = Amount::ZERO));
+    }
+
+    #[test]
+    fn sighash_type_is_committed() {
+        let tx = transaction();
+        let types = [SIGHASH_ALL, SIGHASH_NONE, SIGHASH_SINGLE, SIGHASH_ALL | SIGHASH_ANYONECANPAY];
+        for (i, a) in types.iter().enumerate() {
+            for b in &types[i + 1..] {
+                assert_ne!(digest(&tx, 0, *a), digest(&tx, 0, *b));
+            }
+        }
+        assert!(tx.signature_hash(0, &Script::default(), 0x04, Network::Regtest).is_err());
+        assert_eq!(sighash_from_name("SINGLE|ANYONECANPAY").unwrap(), 0x83);
+        assert!(sighash_from_name("ANYONECANPAY").is_err());
+    }
+
+    #[test]
+    fn check_sig_uses_the_appended_type() {
+        let wallet = wallet(1);
+        let script_code = Script::new_p2pkh(&[0; 20]);
+        let sighash_type = SIGHASH_SINGLE | SIGHASH_ANYONECANPAY;
+        let mut tx = transaction();
+        let signature = tx.sign_input(1, &script_code, sighash_type, Network::Regtest, &wallet).unwrap();
+        assert_eq!(signature.len(), 65);
+        assert_eq!(signature[64], sighash_type);
+
+        // others may add inputs and outputs the signature does not cover
+        tx.vin.push(input(4));
+        tx.vout.push(output(3));
+        let check = |tx: &Transaction, signature: &[u8]| {
+            let checker = TransactionSignatureChecker { tx, input: 1, network: Network::Regtest };
+            checker.check_sig(signature, &wallet.public_key, &script_code)
+        };
+        assert!(check(&tx, &signature));
+
+        let mut other_type = signature.clone();
+        other_type[64] = SIGHASH_ALL;
+        assert!(!check(&tx, &other_type));
+        assert!(!check(&tx, &signature[..64]));
+
+        tx.vout[1].value = Amount::ZERO;
+        assert!(!check(&tx, &signature));
+    }
+}
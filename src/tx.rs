@@ -1,12 +1,39 @@
 use bitcoincash_addr::Address;
+use failure::format_err;
 use log::debug;
 use serde::{Deserialize, Serialize};
+use crate::amount::Amount;
 use crate::errors::Result;
+use crate::script::Script;
 
-/// TXOutputs collects TXOutput
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct TXOutputs {
-    pub outputs: Vec<TXOutput>,
+/// OutPoint identifies an output by the id of its transaction and its index
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OutPoint {
+    pub txid: String,
+    pub vout: i32,
+}
+
+impl OutPoint {
+    pub fn new(txid: &str, vout: i32) -> Self {
+        OutPoint {
+            txid: txid.to_string(),
+            vout,
+        }
+    }
+
+    /// Key returns the database key of the outpoint, prefixed by its txid
+    pub fn key(&self) -> String {
+        format!("{}:{}", self.txid, self.vout)
+    }
+
+    /// FromKey parses a database key built by Key
+    pub fn from_key(key: &[u8]) -> Result<Self> {
+        let key = String::from_utf8(key.to_vec())?;
+        match key.split_once(':') {
+            Some((txid, vout)) => Ok(OutPoint::new(txid, vout.parse()?)),
+            None => Err(format_err!("Invalid outpoint key: {}", key)),
+        }
+    }
 }
 
 /// Transaction Input
@@ -14,42 +41,41 @@ pub struct TXOutputs {
 pub struct TXInput {
     pub txid: String,
     pub vout: i32,
-    pub signature: Vec<u8>,
-    pub pub_key: Vec<u8>,
+    pub script_sig: Script,
+    pub sequence: u32,
 }
 
 /// Transaction Output
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct TXOutput {
-    pub value: i32,
-    pub pub_key_hash: Vec<u8>,
+    pub value: Amount,
+    pub script_pubkey: Script,
 }
 
 impl TXOutput {
-    pub fn new(value: i32, address: String) -> Result<Self> {
+    pub fn new(value: Amount, address: String) -> Result<Self> {
         let mut txo = TXOutput {
             value,
-            pub_key_hash: Vec::new(),
+            script_pubkey: Script::default(),
         };
         txo.lock(&address)?;
         Ok(txo)
     }
 
-    /// Lock signs the output
+    /// NewData creates an unspendable output carrying data
+    pub fn new_data(data: &[u8]) -> Self {
+        TXOutput {
+            value: Amount::ZERO,
+            script_pubkey: Script::new_data(data),
+        }
+    }
+
+    /// Lock locks the output to the public key hash or the redeem script hash
+    /// of the address
     fn lock(&mut self, address: &str) -> Result<()> {
-        let pub_key_hash = Address::decode(address).unwrap().body;
+        let address_data = Address::decode(address).map_err(|_| format_err!("Invalid address {}", address))?;
         debug!("Lock: {}", address);
-        self.pub_key_hash = pub_key_hash;
+        self.script_pubkey = Script::from_address(&address_data);
         Ok(())
     }
-
-    /// IsLockedWithKey checks if the output can be used by the owner of the public key
-    pub fn is_locked_with_key(&self, pub_key_hash: &[u8]) -> bool {
-        self.pub_key_hash == pub_key_hash
-    }
-
-    /// CanUnlockOutputWith checks whether the address initiated the transaction
-    pub fn can_be_unlock_with(&self, unlocking_data: &[u8]) -> bool {
-        self.pub_key_hash == unlocking_data
-    }
 }
\ No newline at end of file
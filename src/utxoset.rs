@@ -1,131 +1,593 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use log::info;
+use failure::format_err;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
 
+use crate::amount::Amount;
 use crate::block::Block;
-use crate::blockchain::Blockchain;
-use crate::errors::Result;
-use crate::tx::TXOutputs;
+use crate::blockchain::{Blockchain, ChainUpdate};
+use crate::errors::{BlockError, Result, TxError};
+use crate::monetary;
+use crate::script::Script;
+use crate::transaction::{
+    Transaction, SEQUENCE_LOCKTIME_DISABLE_FLAG, SEQUENCE_LOCKTIME_GRANULARITY, SEQUENCE_LOCKTIME_MASK,
+    SEQUENCE_LOCKTIME_TYPE_FLAG,
+};
+use crate::tx::{OutPoint, TXOutput};
+
+/// Version of the layout of the UTXO db, bumped whenever entries change shape
+const UTXO_DB_VERSION: u32 = 5;
+const UTXO_TREE: &str = "outpoints";
+
+/// UTXOEntry is an unspent output together with the height of the block
+/// that created it
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UTXOEntry {
+    pub output: TXOutput,
+    pub height: i32,
+    pub is_coinbase: bool,
+}
+
+impl UTXOEntry {
+    /// IsMature checks whether the output may be spent by a block at height.
+    /// Coinbase outputs need maturity confirmations first
+    pub fn is_mature(&self, height: i32, maturity: i32) -> bool {
+        !self.is_coinbase || height - self.height >= maturity
+    }
+}
+
+/// SpentOutput is an output removed from the UTXO set by a block
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SpentOutput {
+    pub outpoint: OutPoint,
+    pub entry: UTXOEntry,
+}
+
+/// BlockUndo records the outputs a block spent, in order, so the block can
+/// be disconnected from the UTXO set
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct BlockUndo {
+    pub spent: Vec<SpentOutput>,
+}
 
 /// UTXOSet represents UTXO set
 pub struct UTXOSet {
     pub blockchain: Blockchain,
+    db: sled::Db,
 }
 
 impl UTXOSet {
-    /// Reindex rebuilds the UTXO set
-    pub fn reindex(&self) -> Result<()> {
-        if let Err(_) = std::fs::remove_dir_all("data/utxos") {
-            info!("There are not any UTXOs to delete.")
+    /// NewUTXOSet opens the UTXO set of the blockchain, rebuilding it when it
+    /// was written with an older layout
+    pub fn new(blockchain: Blockchain) -> Result<UTXOSet> {
+        let db = sled::open(blockchain.config().utxos_path())?;
+        let utxo_set = UTXOSet { blockchain, db };
+        let version = match utxo_set.db.get("VERSION")? {
+            Some(v) => bincode::deserialize(&v)?,
+            None => {
+                // The first layout kept the outputs of every transaction in
+                // the default tree, keyed by txid
+                utxo_set.db.clear()?;
+                1
+            }
+        };
+        if version != UTXO_DB_VERSION {
+            info!("Migrating UTXO set from version {} to {}", version, UTXO_DB_VERSION);
+            utxo_set.reindex()?;
         }
-        let db = sled::open("data/utxos")?;
+        Ok(utxo_set)
+    }
 
-        let utxos = self.blockchain.find_utxo();
+    fn open(&self) -> Result<sled::Tree> {
+        Ok(self.db.open_tree(UTXO_TREE)?)
+    }
 
-        for (txid, outs) in utxos {
-            db.insert(txid.as_bytes(), bincode::serialize(&outs)?)?;
+    /// Reindex rebuilds the UTXO set by replaying the active chain from the
+    /// genesis block, rewriting the undo record of every block
+    pub fn reindex(&self) -> Result<()> {
+        self.db.drop_tree(UTXO_TREE)?;
+
+        for block in self.blockchain.iter_range(0, self.blockchain.get_best_height()?) {
+            self.update(&block)?;
         }
+        self.db.insert("VERSION", bincode::serialize(&UTXO_DB_VERSION)?)?;
+        self.db.flush()?;
 
         Ok(())
     }
 
     /// Update updates the UTXO set with transactions from the Block
     /// The block is considered to be the top of a blockchain
+    /// The spent outputs are stored as the undo record of the block
     pub fn update(&self, block: &Block) -> Result<()> {
-        let db = sled::open("data/utxos")?;
+        let db = self.open()?;
+        let mut undo = BlockUndo::default();
+
+        for tx in block.get_transaction() {
+            if !tx.is_coinbase() {
+                for vin in &tx.vin {
+                    let outpoint = OutPoint::new(&vin.txid, vin.vout);
+                    let entry = db
+                        .remove(outpoint.key())?
+                        .ok_or_else(|| format_err!("Output {} is not in the UTXO set", outpoint.key()))?;
+                    undo.spent.push(SpentOutput {
+                        outpoint,
+                        entry: bincode::deserialize(&entry)?,
+                    });
+                }
+            }
+
+            for (index, out) in tx.vout.iter().enumerate() {
+                if out.script_pubkey.is_unspendable() {
+                    continue;
+                }
+                let outpoint = OutPoint::new(&tx.id, index as i32);
+                let entry = UTXOEntry {
+                    output: out.clone(),
+                    height: block.get_height(),
+                    is_coinbase: tx.is_coinbase(),
+                };
+                db.insert(outpoint.key(), bincode::serialize(&entry)?)?;
+            }
+        }
+
+        self.blockchain.put_undo(&block.get_hash(), &undo)?;
+        Ok(())
+    }
+
+    /// Disconnect reverts the changes Update made for the Block using its undo
+    /// record. The block is considered to be the top of a blockchain
+    pub fn disconnect(&self, block: &Block) -> Result<()> {
+        let undo = self
+            .blockchain
+            .get_undo(&block.get_hash())?
+            .ok_or_else(|| format_err!("No undo data for block {}", block.get_hash()))?;
+        let db = self.open()?;
 
+        let mut spent = undo.spent.iter().rev();
+        for tx in block.get_transaction().iter().rev() {
+            for index in 0..tx.vout.len() {
+                db.remove(OutPoint::new(&tx.id, index as i32).key())?;
+            }
+            if tx.is_coinbase() {
+                continue;
+            }
+            for _ in &tx.vin {
+                let entry = spent
+                    .next()
+                    .ok_or_else(|| format_err!("Undo data of block {} is incomplete", block.get_hash()))?;
+                db.insert(entry.outpoint.key(), bincode::serialize(&entry.entry)?)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// AddBlock stores a block received from a peer and switches the active
+    /// chain to it when it has the most cumulative work
+    pub fn add_block(&mut self, block: Block) -> Result<ChainUpdate> {
+        if self.blockchain.has_block(&block.get_hash())? {
+            return Ok(ChainUpdate::default());
+        }
+        self.blockchain.add_block(block.clone())?;
+        if !self.blockchain.has_more_work(&block.get_hash())? {
+            info!("Block {} stored on a side branch", block.get_hash());
+            return Ok(ChainUpdate::default());
+        }
+        self.reorganize(&block.get_hash())
+    }
+
+    /// Reorganize makes new_tip the tip of the active chain. Blocks after the
+    /// fork point are disconnected using their undo records and the new branch
+    /// is validated and connected block by block. An invalid block is removed
+    /// together with its descendants on the branch and the previous chain is
+    /// restored
+    pub fn reorganize(&mut self, new_tip: &str) -> Result<ChainUpdate> {
+        let old_tip = self.blockchain.get_tip_hash();
+        let fork = self.blockchain.find_fork(&old_tip, new_tip)?;
+        let disconnected = self.blockchain.get_branch(&old_tip, &fork)?;
+        let mut branch = self.blockchain.get_branch(new_tip, &fork)?;
+        branch.reverse();
+
+        if !disconnected.is_empty() {
+            info!("Reorganize from {} to {}, fork at {}", old_tip, new_tip, fork);
+        }
+        for block in &disconnected {
+            self.disconnect_block(block)?;
+        }
+
+        let mut connected = Vec::new();
+        for (i, block) in branch.iter().enumerate() {
+            if let Err(e) = self.connect_block(block) {
+                error!("Block {} failed to connect: {}", block.get_hash(), e);
+                for invalid in &branch[i..] {
+                    self.blockchain.remove_block(&invalid.get_hash())?;
+                }
+                if !disconnected.is_empty() {
+                    for b in connected.iter().rev() {
+                        self.disconnect_block(b)?;
+                    }
+                    for b in disconnected.iter().rev() {
+                        self.update(b)?;
+                        self.blockchain.connect_tip(b)?;
+                    }
+                }
+                return Err(e);
+            }
+            connected.push(block.clone());
+        }
+        Ok(ChainUpdate {
+            disconnected,
+            connected,
+        })
+    }
+
+    /// ConnectBlock validates a block extending the tip, applies it to the UTXO
+    /// set and makes it the new tip
+    fn connect_block(&mut self, block: &Block) -> Result<()> {
+        self.validate_block(block)?;
+        self.update(block)?;
+        self.blockchain.connect_tip(block)
+    }
+
+    /// DisconnectBlock removes the tip block from the UTXO set and makes its
+    /// parent the new tip
+    fn disconnect_block(&mut self, block: &Block) -> Result<()> {
+        self.disconnect(block)?;
+        self.blockchain.disconnect_tip(block)
+    }
+
+    /// ValidateBlock runs the full consensus checks on a block that extends the
+    /// tip: header and coinbase rules, lock times, unspent inputs, input
+    /// signatures and the coinbase claiming no more than the subsidy and fees
+    pub fn validate_block(&self, block: &Block) -> Result<()> {
+        self.blockchain.check_block(block)?;
+
+        let mut block_txs: HashMap<String, Transaction> = HashMap::new();
+        let mut spent: HashSet<(String, i32)> = HashSet::new();
+        let mut fees = Amount::ZERO;
+        let maturity = self.blockchain.config().coinbase_maturity();
+        let median_time = self.blockchain.get_median_time_past(&block.get_prev_hash())?;
         for tx in block.get_transaction() {
+            if !tx.is_final(block.get_height(), (median_time / 1000) as u64) {
+                return Err(BlockError::NonFinalTransaction(tx.id.clone()).into());
+            }
             if !tx.is_coinbase() {
+                let mut entries = Vec::new();
+                let mut input_value = Amount::ZERO;
                 for vin in &tx.vin {
-                    let mut update_outputs = TXOutputs {
-                        outputs: Vec::new(),
+                    if !spent.insert((vin.txid.clone(), vin.vout)) {
+                        return Err(BlockError::DuplicateSpend(vin.txid.clone(), vin.vout).into());
+                    }
+                    let prev = match block_txs.get(&vin.txid) {
+                        Some(prev_tx) if vin.vout >= 0 && (vin.vout as usize) < prev_tx.vout.len() => Some(UTXOEntry {
+                            output: prev_tx.vout[vin.vout as usize].clone(),
+                            height: block.get_height(),
+                            is_coinbase: prev_tx.is_coinbase(),
+                        }),
+                        Some(_) => None,
+                        None => self.find_output(&vin.txid, vin.vout)?,
                     };
-                    let outs: TXOutputs = bincode::deserialize(&db.get(&vin.txid)?.unwrap().to_vec())?;
-                    for out_idx in 0..outs.outputs.len() {
-                        if out_idx != vin.vout as usize {
-                            update_outputs.outputs.push(outs.outputs[out_idx].clone());
+                    match prev {
+                        Some(entry) if !entry.is_mature(block.get_height(), maturity) => {
+                            return Err(BlockError::ImmatureCoinbaseSpend {
+                                txid: tx.id.clone(),
+                                prev_txid: vin.txid.clone(),
+                                vout: vin.vout,
+                            }
+                            .into())
+                        }
+                        Some(entry) => {
+                            input_value = input_value
+                                .checked_add(entry.output.value)
+                                .ok_or_else(|| BlockError::ValueOutOfRange(tx.id.clone()))?;
+                            entries.push(entry);
+                        }
+                        None => {
+                            return Err(BlockError::MissingInput {
+                                txid: tx.id.clone(),
+                                prev_txid: vin.txid.clone(),
+                                vout: vin.vout,
+                            }
+                            .into())
                         }
                     }
+                }
+                if !self.sequence_locks_met(tx, &entries, block.get_height(), median_time)? {
+                    return Err(BlockError::SequenceLocked(tx.id.clone()).into());
+                }
+                let prev_outputs: Vec<TXOutput> = entries.iter().map(|entry| entry.output.clone()).collect();
+                if !tx.verify(&prev_outputs, self.blockchain.config().network())? {
+                    return Err(BlockError::BadSignature(tx.id.clone()).into());
+                }
+                let output_value = Amount::checked_sum(tx.vout.iter().map(|out| out.value))
+                    .ok_or_else(|| BlockError::ValueOutOfRange(tx.id.clone()))?;
+                let fee = input_value
+                    .checked_sub(output_value)
+                    .ok_or_else(|| BlockError::OutputsExceedInputs(tx.id.clone()))?;
+                fees = fees
+                    .checked_add(fee)
+                    .ok_or_else(|| BlockError::ValueOutOfRange(tx.id.clone()))?;
+            }
+            block_txs.insert(tx.id.clone(), tx.clone());
+        }
+
+        let network = self.blockchain.config().network();
+        let cbtx = &block.get_transaction()[0];
+        let reward = monetary::block_subsidy(block.get_height(), network)
+            .checked_add(fees)
+            .ok_or_else(|| BlockError::ValueOutOfRange(cbtx.id.clone()))?;
+        let value = Amount::checked_sum(cbtx.vout.iter().map(|out| out.value))
+            .ok_or_else(|| BlockError::ValueOutOfRange(cbtx.id.clone()))?;
+        if value > reward {
+            return Err(BlockError::BadCoinbaseValue { value, reward }.into());
+        }
+        Ok(())
+    }
+
+    /// FindOutput returns the UTXO entry of an unspent output, or None when it
+    /// does not exist or is already spent
+    pub fn find_output(&self, txid: &str, vout: i32) -> Result<Option<UTXOEntry>> {
+        match self.open()?.get(OutPoint::new(txid, vout).key())? {
+            Some(v) => Ok(Some(bincode::deserialize(&v)?)),
+            None => Ok(None),
+        }
+    }
 
-                    if update_outputs.outputs.is_empty() {
-                        db.remove(&vin.txid)?;
-                    } else {
-                        db.insert(vin.txid.as_bytes(), bincode::serialize(&update_outputs)?)?; 
+    /// ValidateTransaction checks a loose transaction against the UTXO set:
+    /// data outputs must fit the data carrier size, it must be final in the
+    /// next block, every input must be unspent, spent
+    /// once and past its relative lock time, signatures must verify and
+    /// outputs must not exceed inputs. It returns the fee the transaction pays
+    pub fn validate_transaction(&self, tx: &Transaction) -> Result<Amount> {
+        let network = self.blockchain.config().network();
+        if tx.is_coinbase() {
+            return Err(TxError::UnexpectedCoinbase(tx.id.clone()).into());
+        }
+        if tx.vin.is_empty() || tx.vout.is_empty() {
+            return Err(TxError::EmptyTransaction(tx.id.clone()).into());
+        }
+        if tx.hash()? != tx.id {
+            return Err(TxError::BadTransactionId(tx.id.clone()).into());
+        }
+
+        let output_value = match Amount::checked_sum(tx.vout.iter().map(|out| out.value)) {
+            Some(value) if monetary::money_range(value, network) => value,
+            _ => return Err(TxError::ValueOutOfRange(tx.id.clone()).into()),
+        };
+        let data_carrier_size = self.blockchain.config().data_carrier_size();
+        if tx.vout.iter().any(|out| out.script_pubkey.data().is_some_and(|data| data.len() > data_carrier_size)) {
+            return Err(TxError::DataCarrierTooLarge(tx.id.clone(), data_carrier_size).into());
+        }
+
+        let maturity = self.blockchain.config().coinbase_maturity();
+        let height = self.blockchain.get_best_height()? + 1;
+        let median_time = self.blockchain.get_median_time_past(&self.blockchain.get_tip_hash())?;
+        if !tx.is_final(height, (median_time / 1000) as u64) {
+            return Err(TxError::NonFinalTransaction(tx.id.clone()).into());
+        }
+
+        let mut spent = HashSet::new();
+        let mut entries = Vec::new();
+        let mut input_value = Amount::ZERO;
+        for vin in &tx.vin {
+            if !spent.insert(OutPoint::new(&vin.txid, vin.vout)) {
+                return Err(TxError::DuplicateInput {
+                    txid: tx.id.clone(),
+                    prev_txid: vin.txid.clone(),
+                    vout: vin.vout,
+                }
+                .into());
+            }
+            match self.find_output(&vin.txid, vin.vout)? {
+                Some(entry) if !entry.is_mature(height, maturity) => {
+                    return Err(TxError::ImmatureCoinbaseSpend {
+                        txid: tx.id.clone(),
+                        prev_txid: vin.txid.clone(),
+                        vout: vin.vout,
+                    }
+                    .into())
+                }
+                Some(entry) => {
+                    input_value = input_value
+                        .checked_add(entry.output.value)
+                        .ok_or_else(|| TxError::ValueOutOfRange(tx.id.clone()))?;
+                    entries.push(entry);
+                }
+                None => {
+                    return Err(TxError::MissingInput {
+                        txid: tx.id.clone(),
+                        prev_txid: vin.txid.clone(),
+                        vout: vin.vout,
                     }
+                    .into())
                 }
             }
+        }
+        let fee = input_value
+            .checked_sub(output_value)
+            .ok_or_else(|| TxError::OutputsExceedInputs(tx.id.clone()))?;
+        if !self.sequence_locks_met(tx, &entries, height, median_time)? {
+            return Err(TxError::SequenceLocked(tx.id.clone()).into());
+        }
+        let prev_outputs: Vec<TXOutput> = entries.iter().map(|entry| entry.output.clone()).collect();
+        if !tx.verify(&prev_outputs, network)? {
+            return Err(TxError::BadSignature(tx.id.clone()).into());
+        }
+        Ok(fee)
+    }
 
-            let mut new_outputs = TXOutputs {
-                outputs: Vec::new(),
-            };
-            for out in &tx.vout {
-                new_outputs.outputs.push(out.clone());
+    /// SequenceLocksMet checks the relative lock times of the inputs of a
+    /// transaction in a block at height whose previous blocks have the median
+    /// time, in milliseconds. Entries are the outputs spent by the inputs, in
+    /// input order
+    fn sequence_locks_met(&self, tx: &Transaction, entries: &[UTXOEntry], height: i32, median_time: u128) -> Result<bool> {
+        for (vin, entry) in tx.vin.iter().zip(entries) {
+            if vin.sequence & SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+                continue;
+            }
+            let value = vin.sequence & SEQUENCE_LOCKTIME_MASK;
+            if vin.sequence & SEQUENCE_LOCKTIME_TYPE_FLAG != 0 {
+                // time locks count from the median time of the blocks before
+                // the one creating the output
+                let prev_hash = self
+                    .blockchain
+                    .get_block_hash((entry.height - 1).max(0))?
+                    .ok_or_else(|| format_err!("No block at height {}", entry.height - 1))?;
+                let created = self.blockchain.get_median_time_past(&prev_hash)?;
+                let lock = ((value as u128) << SEQUENCE_LOCKTIME_GRANULARITY) * 1000;
+                if median_time < created + lock {
+                    return Ok(false);
+                }
+            } else if height < entry.height + value as i32 {
+                return Ok(false);
             }
-            db.insert(tx.id.as_bytes(), bincode::serialize(&new_outputs)?)?;
         }
-
-        Ok(())
+        Ok(true)
     }
 
     /// CountTransactions returns the number of transactions in the UTXO set
     pub fn count_transactions(&self) -> Result<i32> {
-        let mut counter = 0;
-        let db = sled::open("data/utxos")?;
-        for kv in db.iter() {
-            kv?;
-            counter += 1;
+        let mut txids = HashSet::new();
+        for kv in self.open()?.iter() {
+            let (k, _) = kv?;
+            txids.insert(OutPoint::from_key(&k)?.txid);
         }
-        Ok(counter)
-
+        Ok(txids.len() as i32)
     }
 
-    /// FindUnspentTransactions returns a list of transactions containing unspent outputs
+    /// FindUnspentTransactions returns a list of transactions containing unspent
+    /// outputs the next block may spend, skipping immature coinbase outputs
     pub fn find_spendable_outputs(
         &self,
-        address: &[u8],
-        amount: i32,
-    ) -> Result<(i32, HashMap<String, Vec<i32>>)> {
+        script_pubkey: &Script,
+        amount: Amount,
+    ) -> Result<(Amount, HashMap<String, Vec<i32>>)> {
         let mut unspent_outputs: HashMap<String, Vec<i32>> = HashMap::new();
-        let mut accumulated = 0;
-        let db = sled::open("data/utxos")?;  
+        let mut accumulated = Amount::ZERO;
+        let height = self.blockchain.get_best_height()? + 1;
+        let maturity = self.blockchain.config().coinbase_maturity();
 
-        for kv in db.iter() {
+        for kv in self.open()?.iter() {
             let (k, v) = kv?;
-            let txid = String::from_utf8(k.to_vec())?;
-            let outs: TXOutputs = bincode::deserialize(&v.to_vec())?;
-
-            for out_idx in 0..outs.outputs.len() {
-                if outs.outputs[out_idx].is_locked_with_key(address) && accumulated < amount {
-                    accumulated += outs.outputs[out_idx].value;
-                    match unspent_outputs.get_mut(&txid) {
-                        Some(v) => v.push(out_idx as i32),
-                        None => {
-                            unspent_outputs.insert(txid.clone(), vec![out_idx as i32]);
-                        }
-                    }
-                }
+            let outpoint = OutPoint::from_key(&k)?;
+            let entry: UTXOEntry = bincode::deserialize(&v)?;
+
+            if entry.output.script_pubkey == *script_pubkey
+                && entry.is_mature(height, maturity)
+                && accumulated < amount
+            {
+                accumulated = accumulated
+                    .checked_add(entry.output.value)
+                    .ok_or_else(|| format_err!("Spendable outputs overflow"))?;
+                unspent_outputs
+                    .entry(outpoint.txid)
+                    .or_default()
+                    .push(outpoint.vout);
             }
         }
         Ok((accumulated, unspent_outputs))
     }
 
-    /// FindUTXO finds UTXO for a given public key hash
-    pub fn find_utxo(&self, pub_key_hash: &[u8]) -> Result<TXOutputs> {
-        let mut utxos = TXOutputs {
-            outputs: Vec::new(),
-        };
-        let db = sled::open("data/utxos")?;
+    /// GetBalance returns the spendable and the immature balance of the
+    /// outputs locked by a script
+    pub fn get_balance(&self, script_pubkey: &Script) -> Result<(Amount, Amount)> {
+        let height = self.blockchain.get_best_height()? + 1;
+        let maturity = self.blockchain.config().coinbase_maturity();
+        let mut spendable = Amount::ZERO;
+        let mut immature = Amount::ZERO;
 
-        for kv in db.iter() {
+        for kv in self.open()?.iter() {
             let (_, v) = kv?;
-            let outs: TXOutputs = bincode::deserialize(&v.to_vec())?;
-
-            for out in outs.outputs {
-                if out.can_be_unlock_with(pub_key_hash) {
-                    utxos.outputs.push(out.clone())
-                }
+            let entry: UTXOEntry = bincode::deserialize(&v)?;
+            if entry.output.script_pubkey == *script_pubkey {
+                let balance = if entry.is_mature(height, maturity) {
+                    &mut spendable
+                } else {
+                    &mut immature
+                };
+                *balance = balance
+                    .checked_add(entry.output.value)
+                    .ok_or_else(|| format_err!("Balance overflow"))?;
             }
         }
-        Ok(utxos)
+        Ok((spendable, immature))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, Network};
+    use crate::transaction::SEQUENCE_FINAL;
+    use crate::tx::TXInput;
+    use crate::wallet::Wallet;
+    use crypto::ed25519;
+
+    fn snapshot(utxo_set: &UTXOSet) -> Vec<(sled::IVec, sled::IVec)> {
+        utxo_set.open().unwrap().iter().map(|item| item.unwrap()).collect()
+    }
+
+    fn spend(txid: &str, vout: i32, outputs: usize) -> Transaction {
+        let mut tx = Transaction {
+            id: String::new(),
+            vin: vec![TXInput {
+                txid: txid.to_string(),
+                vout,
+                script_sig: Script::default(),
+                sequence: SEQUENCE_FINAL,
+            }],
+            vout: (0..outputs)
+                .map(|n| TXOutput {
+                    value: Amount::from_base_units(1000 + n as u64),
+                    script_pubkey: Script::new_p2pkh(&[n as u8; 20]),
+                })
+                .collect(),
+            lock_time: 0,
+        };
+        tx.id = tx.hash().unwrap();
+        tx
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn disconnect_restores_update() {
+        let datadir = std::env::temp_dir().join(format!("utxoset-test-{}", std::process::id()));
+        let config = Config::new(datadir.to_str().unwrap(), Network::Regtest);
+        let (private_key, public_key) = ed25519::keypair(&[7; 32]);
+        let wallet = Wallet {
+            private_key: private_key.to_vec(),
+            public_key: public_key.to_vec(),
+        };
+        let address = wallet.get_address(Network::Regtest);
+
+        let blockchain = Blockchain::create_blockchain(&config, address.clone()).unwrap();
+        let genesis = blockchain.get_block_by_height(0).unwrap().unwrap();
+        let utxo_set = UTXOSet::new(blockchain).unwrap();
+        let before = snapshot(&utxo_set);
+
+        let coinbase = Transaction::new_coinbase(address, String::new(), 1, Amount::from_base_units(5000)).unwrap();
+        let parent = spend(&genesis.get_transaction()[0].id, 0, 2);
+        let child = spend(&parent.id, 0, 1);
+        let block = Block::new_block(
+            vec![coinbase, parent.clone(), child.clone()],
+            genesis.get_hash(),
+            1,
+            genesis.get_bits(),
+            0,
+        )
+        .unwrap();
+
+        utxo_set.update(&block).unwrap();
+        assert!(utxo_set.find_output(&genesis.get_transaction()[0].id, 0).unwrap().is_none());
+        assert!(utxo_set.find_output(&parent.id, 0).unwrap().is_none());
+        assert!(utxo_set.find_output(&parent.id, 1).unwrap().is_some());
+        assert!(utxo_set.find_output(&child.id, 0).unwrap().is_some());
+        assert_eq!(utxo_set.blockchain.get_undo(&block.get_hash()).unwrap().unwrap().spent.len(), 2);
+
+        utxo_set.disconnect(&block).unwrap();
+        assert_eq!(snapshot(&utxo_set), before);
+
+        drop(utxo_set);
+        std::fs::remove_dir_all(&datadir).unwrap();
+    }
+}
@@ -1,11 +1,16 @@
 use std::collections::HashMap;
-use bitcoincash_addr::{Address, HashType, Scheme};
+use bitcoincash_addr::{Address, HashType, Network as AddressNetwork, Scheme};
 use crypto::{digest::Digest, ed25519, ripemd160::Ripemd160, sha2::Sha256};
-use log::info;
+use failure::format_err;
+use log::{info, warn};
 use rand::RngCore;
 use rand::rngs::OsRng;
 use serde::{Deserialize, Serialize};
+use crate::config::{Config, Network};
 use crate::errors::Result;
+use crate::script::{self, Script, MAX_PUBKEYS_PER_MULTISIG};
+
+const SCRIPTS_TREE: &str = "scripts";
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Wallet {
@@ -27,19 +32,61 @@ impl Wallet {
         }
     }
 
-    pub fn get_address(&self) -> String {
+    /// GetAddress returns the address of the wallet on the given network.
+    /// Wallets used to have addresses of the script hash type, which now
+    /// denote P2SH outputs: see Wallets::decode_address
+    pub fn get_address(&self, network: Network) -> String {
         let mut pub_hash = self.public_key.clone();
         hash_pub_key(&mut pub_hash);
         let address = Address {
             body: pub_hash,
             scheme: Scheme::Base58,
-            hash_type: HashType::Script,
-            ..Default::default()
+            hash_type: HashType::Key,
+            network: network.address_network(),
         };
         address.encode().unwrap()
     }
 }
 
+/// ScriptAddress returns the address of outputs locked to a redeem script
+pub fn script_address(redeem_script: &Script, network: Network) -> Result<String> {
+    let address = Address {
+        body: script::hash160(&redeem_script.to_bytes()?),
+        scheme: Scheme::Base58,
+        hash_type: HashType::Script,
+        network: network.address_network(),
+    };
+    address.encode().map_err(|_| format_err!("Cannot encode the address of {}", redeem_script))
+}
+
+/// DecodeAddress returns the script locking outputs paid to an address,
+/// failing when the address is malformed or belongs to another network
+pub fn decode_address(address: &str, network: Network) -> Result<Script> {
+    let decoded = Address::decode(address).map_err(|_| format_err!("Invalid address {}", address))?;
+    // test and regtest addresses share their version bytes
+    if (decoded.network == AddressNetwork::Main) != (network == Network::Main) {
+        return Err(format_err!("Address {} is not a {} address", address, network.name()));
+    }
+    Ok(Script::from_address(&decoded))
+}
+
+/// EncodeAddress returns the address outputs locked by the script are paid
+/// to, if it is a P2PKH or P2SH script
+pub fn encode_address(script_pubkey: &Script, network: Network) -> Option<String> {
+    let (body, hash_type) = match (script_pubkey.p2pkh_hash(), script_pubkey.p2sh_hash()) {
+        (Some(hash), _) => (hash, HashType::Key),
+        (_, Some(hash)) => (hash, HashType::Script),
+        _ => return None,
+    };
+    let address = Address {
+        body: body.to_vec(),
+        scheme: Scheme::Base58,
+        hash_type,
+        network: network.address_network(),
+    };
+    address.encode().ok()
+}
+
 // TODO) send to utils file
 pub fn hash_pub_key(pub_key: &mut Vec<u8>) {
     let mut hasher1 = Sha256::new();
@@ -53,29 +100,50 @@ pub fn hash_pub_key(pub_key: &mut Vec<u8>) {
 
 pub struct Wallets {
     wallets: HashMap<String, Wallet>,
+    scripts: HashMap<String, Script>,
+    network: Network,
+    db: sled::Db,
 }
 
 
 impl Wallets {
-    pub fn new() -> Result<Wallets> {
+    pub fn new(config: &Config) -> Result<Wallets> {
         let mut wlt = Wallets {
             wallets: HashMap::<String, Wallet>::new(),
+            scripts: HashMap::<String, Script>::new(),
+            network: config.network(),
+            db: sled::open(config.wallets_path())?,
         };
 
-        let db = sled::open("data/wallets")?;
-        for item in db.into_iter() {
-            let i = item?;
-            let address = String::from_utf8(i.0.to_vec())?;
-            let wallet = bincode::deserialize(&i.1.to_vec())?;
+        let items = wlt.db.iter().collect::<std::result::Result<Vec<_>, _>>()?;
+        for (key, value) in items {
+            let wallet: Wallet = bincode::deserialize(&value)?;
+            // wallets saved before addresses had the key hash type are stored
+            // under their old address, which must not be paid anymore
+            let address = wallet.get_address(wlt.network);
+            if *key != *address.as_bytes() {
+                warn!(
+                    "Wallet address {} is now {}. Coins paid to the old address cannot be spent, hand out the new one",
+                    String::from_utf8_lossy(&key),
+                    address
+                );
+                wlt.db.remove(&key)?;
+                wlt.db.insert(address.as_bytes(), value)?;
+            }
             wlt.wallets.insert(address, wallet);
         }
-        drop(db);
+
+        for item in wlt.db.open_tree(SCRIPTS_TREE)?.iter() {
+            let (key, value) = item?;
+            let address = String::from_utf8(key.to_vec())?;
+            wlt.scripts.insert(address, bincode::deserialize(&value)?);
+        }
         Ok(wlt)
     }
 
     pub fn create_wallet(&mut self) -> String {
         let wallet = Wallet::new();
-        let address = wallet.get_address();
+        let address = wallet.get_address(self.network);
         self.wallets.insert(address.clone(), wallet);
         info!("Wallet {} created", address);
         address
@@ -83,7 +151,7 @@ impl Wallets {
 
     pub fn get_all_addresses(&self) -> Vec<String> {
         let mut addresses = Vec::new();
-        for (address, _) in &self.wallets {
+        for address in self.wallets.keys() {
             addresses.push(address.clone())
         }
         addresses
@@ -93,16 +161,87 @@ impl Wallets {
         self.wallets.get(address)
     }
 
+    /// AddMultisig adds the redeem script requiring m signatures of the public
+    /// keys and returns its address
+    pub fn add_multisig(&mut self, m: usize, pub_keys: &[Vec<u8>]) -> Result<String> {
+        if pub_keys.is_empty() || pub_keys.len() > MAX_PUBKEYS_PER_MULTISIG {
+            return Err(format_err!("A multisig needs 1 to {} public keys", MAX_PUBKEYS_PER_MULTISIG));
+        }
+        if m < 1 || m > pub_keys.len() {
+            return Err(format_err!("A multisig of {} keys needs 1 to {} signatures", pub_keys.len(), pub_keys.len()));
+        }
+        if let Some(key) = pub_keys.iter().find(|key| key.len() != 32) {
+            return Err(format_err!("Invalid public key {}", hex::encode(key)));
+        }
+        if pub_keys.iter().enumerate().any(|(i, key)| pub_keys[..i].contains(key)) {
+            return Err(format_err!("The public keys of a multisig must be distinct"));
+        }
+        let address = self.add_script(Script::new_multisig(m, pub_keys))?;
+        info!("Multisig {} created", address);
+        Ok(address)
+    }
+
+    /// AddScript adds a redeem script to the wallets and returns its address
+    pub fn add_script(&mut self, redeem_script: Script) -> Result<String> {
+        let address = script_address(&redeem_script, self.network)?;
+        self.scripts.insert(address.clone(), redeem_script);
+        Ok(address)
+    }
+
+    pub fn get_script(&self, address: &str) -> Option<&Script> {
+        self.scripts.get(address)
+    }
+
+    /// DecodeAddress decodes an address to pay like the DecodeAddress function,
+    /// but also rejects the legacy address of a wallet. Before addresses had
+    /// the key hash type, wallet addresses had the script hash type, so
+    /// paying them now locks the coins to a script hash nobody can unlock
+    pub fn decode_address(&self, address: &str) -> Result<Script> {
+        let script_pubkey = decode_address(address, self.network)?;
+        if let Some(wallet) = script_pubkey.p2sh_hash().and_then(|hash| self.find_wallet(hash)) {
+            return Err(format_err!(
+                "{} is a legacy wallet address whose coins could never be spent, use {} instead",
+                address,
+                wallet.get_address(self.network)
+            ));
+        }
+        Ok(script_pubkey)
+    }
+
+    /// FindScript returns the redeem script hashing to script_hash
+    pub fn find_script(&self, script_hash: &[u8]) -> Option<&Script> {
+        self.scripts.values().find(|redeem_script| {
+            redeem_script
+                .to_bytes()
+                .is_ok_and(|bytes| script::hash160(&bytes) == script_hash)
+        })
+    }
+
+    pub fn get_wallets(&self) -> impl Iterator<Item = &Wallet> {
+        self.wallets.values()
+    }
+
+    /// FindWallet returns the wallet whose public key hashes to pub_key_hash
+    pub fn find_wallet(&self, pub_key_hash: &[u8]) -> Option<&Wallet> {
+        self.wallets.values().find(|wallet| {
+            let mut hash = wallet.public_key.clone();
+            hash_pub_key(&mut hash);
+            hash == pub_key_hash
+        })
+    }
+
     pub fn save_all(&self) -> Result<()> {
-        let db = sled::open("data/wallets")?;
-        
         for (address, wallet) in &self.wallets {
             let data = bincode::serialize(wallet)?;
-            db.insert(address, data)?;
+            self.db.insert(address, data)?;
+        }
+
+        let scripts = self.db.open_tree(SCRIPTS_TREE)?;
+        for (address, redeem_script) in &self.scripts {
+            scripts.insert(address, bincode::serialize(redeem_script)?)?;
         }
 
-        db.flush()?;
-        drop(db);
+        self.db.flush()?;
         Ok(())
     }
 }
\ No newline at end of file